@@ -0,0 +1,89 @@
+//! Assets being transacted, and their per-asset configuration
+
+#[cfg(test)]
+mod test;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::cents::{Cents, Precision};
+use crate::err::TransactionError;
+
+/// Identifies an asset, e.g. a currency code.
+/// Transactions without an asset are in the default asset, the empty string
+pub type AssetId = String;
+
+/// Number of decimal places of an asset, e.g. `USD=2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetPrecision {
+    pub asset: AssetId,
+    pub decimal_places: usize,
+}
+
+impl FromStr for AssetPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (asset, decimal_places) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <asset>=<decimal places>, got {}", s))?;
+        let decimal_places: usize = decimal_places
+            .parse()
+            .map_err(|_| format!("Invalid number of decimal places: {}", decimal_places))?;
+        Ok(Self {
+            asset: asset.to_owned(),
+            decimal_places,
+        })
+    }
+}
+
+/// The precision of `Cents` amounts in every asset, along with per-asset decimal places,
+/// which cannot exceed it. Assets that are not configured have the full precision
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssetConfig {
+    precision: Precision,
+    decimal_places: HashMap<AssetId, usize>,
+}
+
+impl AssetConfig {
+    pub fn new(precision: Precision, precisions: Vec<AssetPrecision>) -> Result<Self, String> {
+        let max = precision.decimal_places();
+        let mut decimal_places = HashMap::new();
+        for p in precisions {
+            if p.decimal_places > max {
+                return Err(format!(
+                    "Too many decimal places for {}: {}. Max {}",
+                    p.asset, p.decimal_places, max
+                ));
+            }
+            decimal_places.insert(p.asset, p.decimal_places);
+        }
+        Ok(Self {
+            precision,
+            decimal_places,
+        })
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn decimal_places(&self, asset: &str) -> usize {
+        self.decimal_places
+            .get(asset)
+            .copied()
+            .unwrap_or_else(|| self.precision.decimal_places())
+    }
+
+    /// Checks that `amount` has no more decimal places than `asset` allows
+    pub fn validate(&self, asset: &str, amount: Cents) -> Result<(), TransactionError> {
+        let decimal_places = self.decimal_places(asset);
+        if self.precision.decimal_places_of(amount) > decimal_places {
+            return Err(TransactionError::PrecisionExceeded {
+                asset: asset.to_owned(),
+                decimal_places,
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use crate::cents::Cents;
+use crate::err::TransactionError;
+
+use super::*;
+
+#[test]
+fn parse_precision() {
+    assert_eq!(
+        Ok(AssetPrecision {
+            asset: "USD".to_owned(),
+            decimal_places: 2,
+        }),
+        "USD=2".parse()
+    );
+    assert!("USD".parse::<AssetPrecision>().is_err());
+    assert!("USD=a".parse::<AssetPrecision>().is_err());
+}
+
+#[test]
+fn too_many_decimal_places() {
+    assert!(AssetConfig::new(Precision::default(), vec!["USD=5".parse().unwrap()]).is_err());
+}
+
+#[test]
+fn validate() {
+    let config = AssetConfig::new(
+        Precision::default(),
+        vec!["USD=2".parse().unwrap(), "JPY=0".parse().unwrap()],
+    )
+    .unwrap();
+    assert_eq!(Ok(()), config.validate("USD", Cents::new(12300)));
+    assert_eq!(
+        Err(TransactionError::PrecisionExceeded {
+            asset: "USD".to_owned(),
+            decimal_places: 2,
+        }),
+        config.validate("USD", Cents::new(12310))
+    );
+    assert_eq!(Ok(()), config.validate("JPY", Cents::new(20000)));
+    assert!(config.validate("JPY", Cents::new(20100)).is_err());
+    assert_eq!(Ok(()), config.validate("", Cents::new(1)));
+}
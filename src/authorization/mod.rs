@@ -0,0 +1,81 @@
+//! Authorizations reserve a client's funds in held until they are captured or voided,
+//! for card-style flows where the final amount is only known later
+
+#[cfg(test)]
+mod test;
+
+use serde::{Deserialize, Serialize};
+
+use crate::asset::AssetId;
+use crate::cents::Cents;
+
+/// Where an authorization is in its lifecycle. Only open authorizations hold funds
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum AuthorizationState {
+    Open,
+    /// The full amount was captured, possibly over several partial captures
+    Captured,
+    /// Voided, the rest of the amount was released
+    Voided,
+    /// Neither captured nor voided in time, the rest of the amount was released
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Authorization {
+    #[serde(with = "crate::cents::raw")]
+    pub amount: Cents,
+    pub asset: AssetId,
+    /// Funds captured so far, across all partial captures
+    #[serde(with = "crate::cents::raw")]
+    pub captured: Cents,
+    state: AuthorizationState,
+    /// The client's transaction count when the authorization was made, see `expired()`
+    seq: u64,
+}
+
+impl Authorization {
+    pub fn new(amount: Cents, asset: AssetId, seq: u64) -> Self {
+        Self {
+            amount,
+            asset,
+            captured: Cents::ZERO,
+            state: AuthorizationState::Open,
+            seq,
+        }
+    }
+
+    pub fn state(&self) -> AuthorizationState {
+        self.state
+    }
+
+    /// Funds still held for the authorization
+    pub fn remaining(&self) -> Cents {
+        match self.state {
+            AuthorizationState::Open => self.amount.saturating_sub(self.captured),
+            _ => Cents::ZERO,
+        }
+    }
+
+    /// Whether an open authorization made at `self.seq` has outlived `expiry` further
+    /// transactions by the time the client handles transaction number `seq`
+    pub fn expired(&self, seq: u64, expiry: u64) -> bool {
+        self.state == AuthorizationState::Open && seq.saturating_sub(self.seq) > expiry
+    }
+
+    /// Captures `amount` of the held funds. Callers are responsible for checking
+    /// that at most `remaining()` is captured
+    pub fn capture(&mut self, amount: Cents) {
+        // unwrap safety: captured never exceeds the authorized amount
+        self.captured = (self.captured + amount).unwrap();
+        if self.captured == self.amount {
+            self.state = AuthorizationState::Captured;
+        }
+    }
+
+    /// Ends an open authorization without capturing the rest of it,
+    /// either `Voided` or `Expired`
+    pub fn release(&mut self, state: AuthorizationState) {
+        self.state = state;
+    }
+}
@@ -0,0 +1,34 @@
+use crate::cents::Cents;
+
+use super::*;
+
+#[test]
+fn partial_capture() {
+    let mut auth = Authorization::new(Cents::new(10), String::new(), 1);
+    assert_eq!(Cents::new(10), auth.remaining());
+    auth.capture(Cents::new(4));
+    assert_eq!(AuthorizationState::Open, auth.state());
+    assert_eq!(Cents::new(6), auth.remaining());
+    auth.capture(Cents::new(6));
+    assert_eq!(AuthorizationState::Captured, auth.state());
+    assert_eq!(Cents::ZERO, auth.remaining());
+}
+
+#[test]
+fn release() {
+    let mut auth = Authorization::new(Cents::new(10), String::new(), 1);
+    auth.capture(Cents::new(3));
+    auth.release(AuthorizationState::Voided);
+    assert_eq!(Cents::ZERO, auth.remaining());
+    assert_eq!(Cents::new(3), auth.captured);
+}
+
+#[test]
+fn expiry() {
+    let mut auth = Authorization::new(Cents::new(10), String::new(), 5);
+    assert!(!auth.expired(7, 2));
+    assert!(auth.expired(8, 2));
+    auth.release(AuthorizationState::Expired);
+    // only open authorizations expire
+    assert!(!auth.expired(8, 2));
+}
@@ -1,19 +1,204 @@
 //! The cents datatype. Each cent is the smallest atomic unit of the asset type
+//!
+//! How many decimal places a cent is, i.e. the precision, is configured at runtime
+//! and defaults to `DEFAULT_DECIMAL_PLACES`. `Cents` are only a number of the smallest unit,
+//! so they are parsed and displayed through the `Precision` they are at, which each `Engine`
+//! holds in its `AssetConfig`.
 
+pub mod signed;
 #[cfg(test)]
 mod test;
 
-use serde::de::{self, Deserialize, Deserializer, Visitor};
+pub use signed::SignedCents;
+
+use std::convert::TryFrom;
 use std::fmt;
+use std::iter;
 use std::ops;
+use std::str::FromStr;
+
+use crate::err::{ArithmeticError, CentsError};
+
+pub const DEFAULT_DECIMAL_PLACES: usize = 4;
+/// 10^18 is the largest power of 10 that fits in a u64, leaving room for at least 18 whole units
+pub const MAX_DECIMAL_PLACES: usize = 18;
+
+/// Number of decimal places in a whole unit of the asset
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Precision {
+    decimal_places: usize,
+    whole_multiplier: u64,
+}
+
+impl Precision {
+    /// Returns None if `decimal_places` > `MAX_DECIMAL_PLACES`
+    pub fn new(decimal_places: usize) -> Option<Self> {
+        if decimal_places > MAX_DECIMAL_PLACES {
+            return None;
+        }
+        Some(Self {
+            decimal_places,
+            whole_multiplier: 10_u64.pow(decimal_places as u32),
+        })
+    }
+
+    pub fn decimal_places(&self) -> usize {
+        self.decimal_places
+    }
+
+    /// Parses a non-negative decimal number with up to `decimal_places` decimal places exactly
+    pub fn parse(&self, s: &str) -> Result<Cents, CentsError> {
+        let decimal_index = match s.find('.') {
+            Some(i) => i,
+            None => {
+                let whole: u64 = s.parse()?;
+                let cents = whole.checked_mul(self.whole_multiplier).ok_or_else(|| {
+                    CentsError::Overflow(format!("whole {} * {}", whole, self.whole_multiplier))
+                })?;
+                return Ok(Cents::new(cents));
+            }
+        };
+        let decimal_places = s.len() - decimal_index - 1;
+        if decimal_places > self.decimal_places {
+            return Err(CentsError::TooManyDecimalPlaces {
+                found: decimal_places,
+                max: self.decimal_places,
+            });
+        }
+        let whole: u64 = s[..decimal_index].parse()?;
+        let decimal: u64 = s[decimal_index + 1..].parse()?;
+        let whole_cents = whole.checked_mul(self.whole_multiplier).ok_or_else(|| {
+            CentsError::Overflow(format!("whole {} * {}", whole, self.whole_multiplier))
+        })?;
+        let decimal_multiplier = 10_u64.pow((self.decimal_places - decimal_places) as u32);
+        let decimal_cents = decimal * decimal_multiplier;
+        let cents = whole_cents.checked_add(decimal_cents).ok_or_else(|| {
+            CentsError::Overflow(format!("whole {} + decimal {}", whole_cents, decimal_cents))
+        })?;
+        Ok(Cents::new(cents))
+    }
+
+    /// Like `parse()`, with an optional leading `-` or `+`
+    pub fn parse_signed(&self, s: &str) -> Result<SignedCents, CentsError> {
+        if let Some(magnitude) = s.strip_prefix('-') {
+            return Ok(SignedCents::debit(self.parse(magnitude)?));
+        }
+        let magnitude = s.strip_prefix('+').unwrap_or(s);
+        Ok(SignedCents::credit(self.parse(magnitude)?))
+    }
+
+    /// Displays `cents` to its full precision
+    pub fn display(&self, cents: Cents) -> CentsDisplay {
+        self.display_places(cents, self.decimal_places)
+    }
 
-pub const MAX_DECIMAL_PLACES: usize = 4;
-const WHOLE_MULTIPLIER: u64 = 10_u64.pow(MAX_DECIMAL_PLACES as u32);
+    /// Displays `cents` to exactly `decimal_places` decimal places, at most the full precision.
+    /// Digits beyond that are truncated, so `decimal_places` should be at least
+    /// `self.decimal_places_of(cents)`
+    pub fn display_places(&self, cents: Cents, decimal_places: usize) -> CentsDisplay {
+        self.display_wide(cents.value().into(), decimal_places)
+    }
+
+    /// Like `display_places()`, for signed amounts and results of wide arithmetic
+    /// that may exceed `Cents::MAX`
+    pub fn display_wide(&self, value: i128, decimal_places: usize) -> CentsDisplay {
+        CentsDisplay {
+            value,
+            precision: *self,
+            decimal_places: decimal_places.min(self.decimal_places),
+        }
+    }
+
+    /// `value` cents at this precision, which display on their own
+    pub fn amount<T: Into<i128>>(&self, value: T) -> Amount {
+        Amount {
+            value: value.into(),
+            precision: *self,
+        }
+    }
+
+    /// Minimum number of decimal places needed to represent `cents` exactly
+    pub fn decimal_places_of(&self, cents: Cents) -> usize {
+        let mut decimals = cents.value() % self.whole_multiplier;
+        let mut places = self.decimal_places;
+        while places > 0 && decimals.is_multiple_of(10) {
+            decimals /= 10;
+            places -= 1;
+        }
+        places
+    }
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        // unwrap safety: DEFAULT_DECIMAL_PLACES <= MAX_DECIMAL_PLACES
+        Self::new(DEFAULT_DECIMAL_PLACES).unwrap()
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
 
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let decimal_places: usize = s
+            .parse()
+            .map_err(|_| format!("Invalid number of decimal places: {}", s))?;
+        Self::new(decimal_places).ok_or_else(|| {
+            format!(
+                "Too many decimal places: {}. Max {}",
+                decimal_places, MAX_DECIMAL_PLACES
+            )
+        })
+    }
+}
+
+/// How to round results that cannot be represented exactly in cents
 #[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rounding {
+    /// To the nearest cent, ties to the nearest even cent (banker's rounding)
+    HalfEven,
+    /// Towards zero
+    Down,
+    /// Away from zero
+    Up,
+}
+
+impl Rounding {
+    /// Divides `dividend` by `divisor`, rounding the quotient.
+    /// `divisor` must be nonzero
+    fn div(self, dividend: u128, divisor: u128) -> u128 {
+        let quotient = dividend / divisor;
+        let remainder = dividend % divisor;
+        if remainder == 0 {
+            return quotient;
+        }
+        let round_up = match self {
+            Self::Down => false,
+            Self::Up => true,
+            // compare remainder against half the divisor without losing the odd bit
+            Self::HalfEven => match (remainder * 2).cmp(&divisor) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Greater => true,
+                std::cmp::Ordering::Equal => quotient % 2 == 1,
+            },
+        };
+        if round_up {
+            quotient + 1
+        } else {
+            quotient
+        }
+    }
+}
+
+/// A non-negative amount in the smallest unit of a `Precision`. The same number
+/// of cents is a different amount at another precision
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct Cents(u64);
 
 impl Cents {
+    pub const ZERO: Cents = Cents(0);
+    pub const MAX: Cents = Cents(u64::MAX);
+
     pub fn new(cents: u64) -> Self {
         Self(cents)
     }
@@ -21,108 +206,182 @@ impl Cents {
     pub fn value(&self) -> u64 {
         self.0
     }
+
+    pub fn checked_add(self, rhs: Cents) -> Result<Cents, ArithmeticError> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Self::new)
+            .ok_or(ArithmeticError::Overflow)
+    }
+
+    pub fn checked_sub(self, rhs: Cents) -> Result<Cents, ArithmeticError> {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Self::new)
+            .ok_or(ArithmeticError::Underflow)
+    }
+
+    pub fn saturating_add(self, rhs: Cents) -> Cents {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    pub fn saturating_sub(self, rhs: Cents) -> Cents {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Multiplies by an integer, e.g. a quantity
+    pub fn checked_mul(self, rhs: u64) -> Result<Cents, ArithmeticError> {
+        self.0
+            .checked_mul(rhs)
+            .map(Self::new)
+            .ok_or(ArithmeticError::Overflow)
+    }
+
+    /// Multiplies by the rational rate `numerator / denominator`, e.g. a fee or interest rate
+    /// of 1.5% is `checked_mul_rate(15, 1000, rounding)`.
+    /// The intermediate product is computed at double width, so only the rounded result
+    /// has to fit in `Cents`
+    pub fn checked_mul_rate(
+        self,
+        numerator: u64,
+        denominator: u64,
+        rounding: Rounding,
+    ) -> Result<Cents, ArithmeticError> {
+        if denominator == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        let product = u128::from(self.0) * u128::from(numerator);
+        let result = rounding.div(product, u128::from(denominator));
+        to_cents(result)
+    }
+
+    /// Divides by an integer, e.g. to split an amount into equal parts
+    pub fn checked_div(self, divisor: u64, rounding: Rounding) -> Result<Cents, ArithmeticError> {
+        self.checked_mul_rate(1, divisor, rounding)
+    }
+
+    /// Double-width sum of `self` and `rhs`, which never overflows
+    pub fn wide_add(self, rhs: Cents) -> u128 {
+        u128::from(self.0) + u128::from(rhs.0)
+    }
+
+    /// Double-width product of `self` and `rhs`, which never overflows
+    pub fn wide_mul(self, rhs: u64) -> u128 {
+        u128::from(self.0) * u128::from(rhs)
+    }
+}
+
+fn to_cents(value: u128) -> Result<Cents, ArithmeticError> {
+    u64::try_from(value)
+        .map(Cents::new)
+        .map_err(|_| ArithmeticError::Overflow)
 }
 
-/// Checked add, returns None if overflowed
+/// Checked add, see `Cents::checked_add()`
 impl ops::Add<Cents> for Cents {
-    type Output = Option<Cents>;
+    type Output = Result<Cents, ArithmeticError>;
 
-    fn add(self, rhs: Cents) -> Option<Cents> {
-        self.value()
-            .checked_add(rhs.value())
-            .map(|res| Self::new(res))
+    fn add(self, rhs: Cents) -> Self::Output {
+        self.checked_add(rhs)
     }
 }
 
-/// Checked sub, returns None if overflowed
+/// Checked sub, see `Cents::checked_sub()`
 impl ops::Sub<Cents> for Cents {
-    type Output = Option<Cents>;
+    type Output = Result<Cents, ArithmeticError>;
+
+    fn sub(self, rhs: Cents) -> Self::Output {
+        self.checked_sub(rhs)
+    }
+}
+
+/// Checked sum, e.g. `amounts.into_iter().sum::<Result<Cents, _>>()`
+impl iter::Sum<Cents> for Result<Cents, ArithmeticError> {
+    fn sum<I: Iterator<Item = Cents>>(mut iter: I) -> Self {
+        iter.try_fold(Cents::ZERO, Cents::checked_add)
+    }
+}
 
-    fn sub(self, rhs: Cents) -> Option<Cents> {
-        self.value()
-            .checked_sub(rhs.value())
-            .map(|res| Self::new(res))
+impl<'a> iter::Sum<&'a Cents> for Result<Cents, ArithmeticError> {
+    fn sum<I: Iterator<Item = &'a Cents>>(iter: I) -> Self {
+        iter.copied().sum()
     }
 }
 
-impl fmt::Display for Cents {
+impl From<Cents> for i128 {
+    fn from(cents: Cents) -> Self {
+        cents.value().into()
+    }
+}
+
+pub struct CentsDisplay {
+    value: i128,
+    precision: Precision,
+    decimal_places: usize,
+}
+
+impl fmt::Display for CentsDisplay {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0 / WHOLE_MULTIPLIER)?;
-        write!(f, ".")?;
-        let decimals = self.0 % WHOLE_MULTIPLIER;
-        if decimals == 0 {
-            return write!(f, "0000");
+        if self.value < 0 {
+            write!(f, "-")?;
         }
-        // leading zeros
-        let mut decimals_copy = decimals;
-        while decimals_copy < (WHOLE_MULTIPLIER / 10 - 1) {
-            write!(f, "0")?;
-            decimals_copy *= 10;
+        let value = self.value.unsigned_abs();
+        let whole_multiplier = u128::from(self.precision.whole_multiplier);
+        write!(f, "{}", value / whole_multiplier)?;
+        if self.decimal_places == 0 {
+            return Ok(());
         }
-        write!(f, "{}", decimals)
+        let truncated_places = (self.precision.decimal_places - self.decimal_places) as u32;
+        let decimals = (value % whole_multiplier) / 10_u128.pow(truncated_places);
+        write!(f, ".{:0width$}", decimals, width = self.decimal_places)
     }
 }
 
-struct CentsVisitor;
+/// An amount of cents along with the precision they are at, e.g. in error messages
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Amount {
+    value: i128,
+    precision: Precision,
+}
 
-impl<'de> Visitor<'de> for CentsVisitor {
-    type Value = Cents;
+impl Amount {
+    pub fn value(&self) -> i128 {
+        self.value
+    }
 
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            formatter,
-            "A number with up to {} decimal places",
-            MAX_DECIMAL_PLACES
-        )
+    pub fn precision(&self) -> Precision {
+        self.precision
     }
+}
+
+/// Displays to the full precision
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precision = self.precision;
+        precision
+            .display_wide(self.value, precision.decimal_places)
+            .fmt(f)
+    }
+}
+
+/// (De)serializes `Cents` as the raw number of cents, for persisting engine state.
+/// Use with `#[serde(with = "crate::cents::raw")]`
+pub(crate) mod raw {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Cents;
 
-    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    pub fn serialize<S>(cents: &Cents, serializer: S) -> Result<S::Ok, S::Error>
     where
-        E: de::Error,
+        S: Serializer,
     {
-        let decimal_index = match s.find(".") {
-            Some(i) => i,
-            None => {
-                let whole: u64 = s.parse().map_err(de::Error::custom)?;
-                return Ok(Self::Value::new(whole * WHOLE_MULTIPLIER));
-            }
-        };
-        let decimal_places = s.len() - decimal_index - 1;
-        if decimal_places > MAX_DECIMAL_PLACES {
-            return Err(de::Error::custom(format!(
-                "Too many decimal places: {}. Max {}",
-                decimal_places, MAX_DECIMAL_PLACES,
-            )));
-        }
-        let whole: u64 = s[..decimal_index].parse().map_err(de::Error::custom)?;
-        let decimal: u64 = s[decimal_index + 1..].parse().map_err(de::Error::custom)?;
-        let whole_cents = whole
-            .checked_mul(WHOLE_MULTIPLIER)
-            .ok_or_else(|| overflow_error(&format!("whole {} * {}", whole, WHOLE_MULTIPLIER)))?;
-        let decimal_multiplier = 10_u64.pow((MAX_DECIMAL_PLACES - decimal_places) as u32);
-        let decimal_cents = decimal * decimal_multiplier;
-        let cents = whole_cents.checked_add(decimal_cents).ok_or_else(|| {
-            overflow_error(&format!(
-                "whole {} + decimal {}",
-                whole_cents, decimal_cents
-            ))
-        })?;
-        Ok(Self::Value::new(cents))
+        serializer.serialize_u64(cents.value())
     }
-}
 
-impl<'de> Deserialize<'de> for Cents {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Cents, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_str(CentsVisitor)
+        u64::deserialize(deserializer).map(Cents::new)
     }
 }
-
-fn overflow_error<E>(attempted_op: &str) -> E
-where
-    E: de::Error,
-{
-    de::Error::custom(&format!("Overflow error: {}", attempted_op))
-}
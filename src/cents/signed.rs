@@ -0,0 +1,151 @@
+//! A signed amount of cents, for transactions such as adjustments that can go either way
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use super::Cents;
+use crate::err::ArithmeticError;
+
+/// Stored as sign and magnitude, so that every `Cents` amount can be negated.
+/// Zero is never negative
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SignedCents {
+    negative: bool,
+    magnitude: Cents,
+}
+
+impl SignedCents {
+    pub const ZERO: SignedCents = SignedCents {
+        negative: false,
+        magnitude: Cents::ZERO,
+    };
+
+    pub fn new(cents: i64) -> Self {
+        let magnitude = Cents::new(cents.unsigned_abs());
+        if cents < 0 {
+            Self::debit(magnitude)
+        } else {
+            Self::credit(magnitude)
+        }
+    }
+
+    /// The amount as a widened number of cents
+    pub fn value(&self) -> i128 {
+        let magnitude = i128::from(self.magnitude.value());
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    pub fn credit(magnitude: Cents) -> Self {
+        Self {
+            negative: false,
+            magnitude,
+        }
+    }
+
+    pub fn debit(magnitude: Cents) -> Self {
+        Self {
+            negative: magnitude != Cents::ZERO,
+            magnitude,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn magnitude(&self) -> Cents {
+        self.magnitude
+    }
+
+    /// The opposite amount, e.g. to reverse an adjustment
+    pub fn negate(self) -> Self {
+        if self.negative {
+            Self::credit(self.magnitude)
+        } else {
+            Self::debit(self.magnitude)
+        }
+    }
+
+    /// `self + rhs`, failing if the magnitude of the result exceeds `Cents::MAX`
+    pub fn checked_add(self, rhs: Cents) -> Result<SignedCents, ArithmeticError> {
+        from_wide(self.value() + i128::from(rhs.value()))
+    }
+
+    /// `self - rhs`, which may be negative.
+    /// Fails if the magnitude of the result exceeds `Cents::MAX`
+    pub fn checked_sub(self, rhs: Cents) -> Result<SignedCents, ArithmeticError> {
+        from_wide(self.value() - i128::from(rhs.value()))
+    }
+}
+
+fn from_wide(value: i128) -> Result<SignedCents, ArithmeticError> {
+    let magnitude = u64::try_from(value.unsigned_abs()).map_err(|_| ArithmeticError::Overflow)?;
+    if value < 0 {
+        Ok(SignedCents::debit(Cents::new(magnitude)))
+    } else {
+        Ok(SignedCents::credit(Cents::new(magnitude)))
+    }
+}
+
+impl Ord for SignedCents {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value().cmp(&other.value())
+    }
+}
+
+impl PartialOrd for SignedCents {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Fails with `ArithmeticError::Underflow` for negative amounts
+impl TryFrom<SignedCents> for Cents {
+    type Error = ArithmeticError;
+
+    fn try_from(cents: SignedCents) -> Result<Self, Self::Error> {
+        if cents.negative {
+            return Err(ArithmeticError::Underflow);
+        }
+        Ok(cents.magnitude)
+    }
+}
+
+impl From<Cents> for SignedCents {
+    fn from(cents: Cents) -> Self {
+        Self::credit(cents)
+    }
+}
+
+impl From<SignedCents> for i128 {
+    fn from(cents: SignedCents) -> Self {
+        cents.value()
+    }
+}
+
+/// (De)serializes `SignedCents` as the raw number of cents, for persisting engine state.
+/// Use with `#[serde(with = "crate::cents::signed::raw")]`
+pub(crate) mod raw {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{from_wide, SignedCents};
+
+    pub fn serialize<S>(cents: &SignedCents, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i128(cents.value())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SignedCents, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        from_wide(i128::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
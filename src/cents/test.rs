@@ -1,16 +1,14 @@
-use csv::StringRecord;
+use crate::err::{ArithmeticError, CentsError};
 
-use super::Cents;
+use super::*;
 
 fn check_eq(ser: &str, de: u64) {
-    let record = StringRecord::from(vec![ser]);
-    let cents: Cents = record.deserialize(None).unwrap();
+    let cents = Precision::default().parse(ser).unwrap();
     assert_eq!(de, cents.value());
 }
 
 fn check_err(ser: &str) {
-    let record = StringRecord::from(vec![ser]);
-    assert!(record.deserialize::<Cents>(None).is_err());
+    assert!(Precision::default().parse(ser).is_err());
 }
 
 #[test]
@@ -42,16 +40,254 @@ fn malformed_input() {
 
 #[test]
 fn display() {
+    let precision = Precision::default();
     let cents = Cents::new(12345);
-    assert_eq!("1.2345", format!("{}", cents));
+    assert_eq!("1.2345", format!("{}", precision.display(cents)));
     let cents = Cents::new(12000);
-    assert_eq!("1.2000", format!("{}", cents));
+    assert_eq!("1.2000", format!("{}", precision.display(cents)));
     let cents = Cents::new(69);
-    assert_eq!("0.0069", format!("{}", cents));
+    assert_eq!("0.0069", format!("{}", precision.display(cents)));
     let cents = Cents::new(690);
-    assert_eq!("0.0690", format!("{}", cents));
+    assert_eq!("0.0690", format!("{}", precision.display(cents)));
     let cents = Cents::new(100234);
-    assert_eq!("10.0234", format!("{}", cents));
+    assert_eq!("10.0234", format!("{}", precision.display(cents)));
     let cents = Cents::new(10000);
-    assert_eq!("1.0000", format!("{}", cents));
+    assert_eq!("1.0000", format!("{}", precision.display(cents)));
+}
+
+#[test]
+fn display_decimal_places() {
+    let precision = Precision::default();
+    let cents = Cents::new(12300);
+    assert_eq!("1.23", format!("{}", precision.display_places(cents, 2)));
+    assert_eq!("1.230", format!("{}", precision.display_places(cents, 3)));
+    assert_eq!("1", format!("{}", precision.display_places(cents, 0)));
+    let cents = Cents::new(100);
+    assert_eq!("0.01", format!("{}", precision.display_places(cents, 2)));
+}
+
+#[test]
+fn decimal_places() {
+    let precision = Precision::default();
+    assert_eq!(0, precision.decimal_places_of(Cents::new(0)));
+    assert_eq!(0, precision.decimal_places_of(Cents::new(20000)));
+    assert_eq!(1, precision.decimal_places_of(Cents::new(21000)));
+    assert_eq!(2, precision.decimal_places_of(Cents::new(100)));
+    assert_eq!(4, precision.decimal_places_of(Cents::new(12345)));
+}
+
+#[test]
+fn parse_precision() {
+    let fiat = Precision::new(2).unwrap();
+    assert_eq!(Ok(Cents::new(12345)), fiat.parse("123.45"));
+    assert_eq!(Ok(Cents::new(12340)), fiat.parse("123.4"));
+    assert_eq!(Ok(Cents::new(12300)), fiat.parse("123"));
+    assert_eq!(
+        Err(CentsError::TooManyDecimalPlaces { found: 3, max: 2 }),
+        fiat.parse("1.234")
+    );
+    let crypto = Precision::new(8).unwrap();
+    assert_eq!(Ok(Cents::new(100_000_001)), crypto.parse("1.00000001"));
+    assert_eq!(Ok(Cents::new(1)), crypto.parse("0.00000001"));
+    assert!(crypto.parse("1.000000001").is_err());
+    let whole = Precision::new(0).unwrap();
+    assert_eq!(Ok(Cents::new(7)), whole.parse("7"));
+    assert!(whole.parse("7.0").is_err());
+}
+
+#[test]
+fn parse_precision_overflow() {
+    let max = Precision::new(MAX_DECIMAL_PLACES).unwrap();
+    assert_eq!(Ok(Cents::new(18_000_000_000_000_000_000)), max.parse("18"));
+    assert!(matches!(max.parse("19"), Err(CentsError::Overflow(_))));
+    assert!(matches!(
+        max.parse("18.446744073709551616"),
+        Err(CentsError::Overflow(_))
+    ));
+    assert!(Precision::new(MAX_DECIMAL_PLACES + 1).is_none());
+}
+
+#[test]
+fn display_precision() {
+    let fiat = Precision::new(2).unwrap();
+    assert_eq!("1.05", format!("{}", fiat.display(Cents::new(105))));
+    assert_eq!("0.00", format!("{}", fiat.display(Cents::new(0))));
+    let crypto = Precision::new(8).unwrap();
+    assert_eq!("0.00000042", format!("{}", crypto.display(Cents::new(42))));
+    assert_eq!(
+        "0.000000",
+        format!("{}", crypto.display_places(Cents::new(42), 6))
+    );
+    assert_eq!(6, crypto.decimal_places_of(Cents::new(100)));
+    let whole = Precision::new(0).unwrap();
+    assert_eq!("42", format!("{}", whole.display(Cents::new(42))));
+}
+
+#[test]
+fn precision_from_str() {
+    assert_eq!(Ok(Precision::new(8).unwrap()), "8".parse());
+    assert!("19".parse::<Precision>().is_err());
+    assert!("-1".parse::<Precision>().is_err());
+}
+
+#[test]
+fn add_sub() {
+    assert_eq!(Ok(Cents::new(5)), Cents::new(2) + Cents::new(3));
+    assert_eq!(Err(ArithmeticError::Overflow), Cents::MAX + Cents::new(1));
+    assert_eq!(Ok(Cents::new(1)), Cents::new(3) - Cents::new(2));
+    assert_eq!(
+        Err(ArithmeticError::Underflow),
+        Cents::new(2) - Cents::new(3)
+    );
+    assert_eq!(Cents::MAX, Cents::MAX.saturating_add(Cents::new(1)));
+    assert_eq!(Cents::ZERO, Cents::new(2).saturating_sub(Cents::new(3)));
+    assert_eq!(u128::from(u64::MAX) + 1, Cents::MAX.wide_add(Cents::new(1)));
+}
+
+#[test]
+fn mul() {
+    assert_eq!(Ok(Cents::new(30)), Cents::new(10).checked_mul(3));
+    assert_eq!(Err(ArithmeticError::Overflow), Cents::MAX.checked_mul(2));
+    assert_eq!(u128::from(u64::MAX) * 2, Cents::MAX.wide_mul(2));
+}
+
+#[test]
+fn mul_rate() {
+    // 1.5% of 100.0000
+    assert_eq!(
+        Ok(Cents::new(15000)),
+        Cents::new(1_000_000).checked_mul_rate(15, 1000, Rounding::HalfEven)
+    );
+    // 1/3 of 0.0010
+    assert_eq!(
+        Ok(Cents::new(3)),
+        Cents::new(10).checked_mul_rate(1, 3, Rounding::HalfEven)
+    );
+    assert_eq!(
+        Ok(Cents::new(4)),
+        Cents::new(10).checked_mul_rate(1, 3, Rounding::Up)
+    );
+    // intermediate product exceeds u64 but the result doesn't
+    assert_eq!(
+        Ok(Cents::MAX),
+        Cents::MAX.checked_mul_rate(3, 3, Rounding::Down)
+    );
+    assert_eq!(
+        Err(ArithmeticError::Overflow),
+        Cents::MAX.checked_mul_rate(4, 3, Rounding::Down)
+    );
+    assert_eq!(
+        Err(ArithmeticError::DivisionByZero),
+        Cents::new(1).checked_mul_rate(1, 0, Rounding::Down)
+    );
+}
+
+#[test]
+fn div_rounding() {
+    let check = |cents: u64, divisor: u64, half_even: u64, down: u64, up: u64| {
+        let cents = Cents::new(cents);
+        assert_eq!(
+            Ok(Cents::new(half_even)),
+            cents.checked_div(divisor, Rounding::HalfEven)
+        );
+        assert_eq!(
+            Ok(Cents::new(down)),
+            cents.checked_div(divisor, Rounding::Down)
+        );
+        assert_eq!(Ok(Cents::new(up)), cents.checked_div(divisor, Rounding::Up));
+    };
+    check(10, 2, 5, 5, 5);
+    check(10, 4, 2, 2, 3);
+    check(14, 4, 4, 3, 4);
+    check(11, 4, 3, 2, 3);
+    check(9, 4, 2, 2, 3);
+    check(0, 7, 0, 0, 0);
+    assert_eq!(
+        Err(ArithmeticError::DivisionByZero),
+        Cents::new(1).checked_div(0, Rounding::Up)
+    );
+}
+
+#[test]
+fn sum() {
+    let amounts = [Cents::new(1), Cents::new(2), Cents::new(3)];
+    assert_eq!(Ok(Cents::new(6)), amounts.iter().sum());
+    assert_eq!(Ok(Cents::ZERO), Vec::<Cents>::new().into_iter().sum());
+    assert_eq!(
+        Err(ArithmeticError::Overflow),
+        vec![Cents::MAX, Cents::new(1)].into_iter().sum()
+    );
+}
+
+#[test]
+fn ord() {
+    let mut amounts = vec![Cents::new(3), Cents::new(1), Cents::new(2)];
+    amounts.sort();
+    assert_eq!(vec![Cents::new(1), Cents::new(2), Cents::new(3)], amounts);
+    assert!(Cents::new(1) < Cents::new(2));
+}
+
+#[test]
+fn default_precision() {
+    let precision = Precision::default();
+    assert_eq!(Ok(Cents::new(12345)), precision.parse("1.2345"));
+    assert_eq!(
+        Err(CentsError::TooManyDecimalPlaces { found: 5, max: 4 }),
+        precision.parse("1.23456")
+    );
+}
+
+#[test]
+fn amount() {
+    let fiat = Precision::new(2).unwrap();
+    let amount = fiat.amount(Cents::new(1234));
+    assert_eq!(1234, amount.value());
+    assert_eq!(fiat, amount.precision());
+    assert_eq!("12.34", amount.to_string());
+    assert_eq!("0.1234", Precision::default().amount(1234).to_string());
+    assert_eq!("-0.05", fiat.amount(SignedCents::new(-5)).to_string());
+}
+
+#[test]
+fn signed() {
+    let precision = Precision::default();
+    let debit = precision.parse_signed("-1.5").unwrap();
+    assert!(debit.is_negative());
+    assert_eq!(Cents::new(15000), debit.magnitude());
+    assert_eq!("-1.5000", precision.amount(debit).to_string());
+    assert_eq!(SignedCents::credit(Cents::new(15000)), debit.negate());
+    assert_eq!(
+        Ok(SignedCents::credit(Cents::new(1))),
+        precision.parse_signed("+0.0001")
+    );
+    // zero is never negative
+    assert!(!precision.parse_signed("-0").unwrap().is_negative());
+    assert_eq!(
+        "0.0000",
+        precision
+            .amount(SignedCents::debit(Cents::ZERO))
+            .to_string()
+    );
+    assert!(precision.parse_signed("--1").is_err());
+}
+
+#[test]
+fn signed_arithmetic() {
+    let debt = SignedCents::new(3).checked_sub(Cents::new(5)).unwrap();
+    assert_eq!(SignedCents::new(-2), debt);
+    let precision = Precision::default();
+    assert_eq!("-0.0002", precision.amount(debt).to_string());
+    assert_eq!("-0.00", precision.display_wide(debt.value(), 2).to_string());
+    assert_eq!(Ok(SignedCents::new(1)), debt.checked_add(Cents::new(3)));
+    assert!(debt < SignedCents::ZERO);
+    assert!(SignedCents::new(-3) < debt);
+    assert_eq!(Err(ArithmeticError::Underflow), Cents::try_from(debt));
+    assert_eq!(
+        Err(ArithmeticError::Overflow),
+        SignedCents::new(1).checked_add(Cents::MAX)
+    );
+    assert_eq!(
+        Err(ArithmeticError::Overflow),
+        SignedCents::new(-1).checked_sub(Cents::MAX)
+    );
 }
@@ -1,41 +1,46 @@
 //! The Client struct, represents the state of the funds of an individual client's account.
+//! A client holds a separate balance for every asset it has transacted in.
 
 #[cfg(test)]
 mod test;
 
-use std::collections::{HashMap, HashSet};
-use std::convert::TryInto;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
 
-use crate::cents::Cents;
+use crate::asset::AssetId;
+use crate::authorization::{Authorization, AuthorizationState};
+use crate::cents::{Cents, Precision, SignedCents};
 use crate::err::TransactionError;
+use crate::policy::Policy;
 use crate::transaction::{
-    DisputableTransaction, DisputableTransactionType, Transaction, TransactionId, TransactionType,
+    DisputableTransaction, DisputableTransactionType, DisputeState, Timestamp, Transaction,
+    TransactionId, TransactionType,
 };
 
 pub type ClientId = u16;
 
-pub struct Client {
-    available: Cents,
+/// A client's funds in a single asset
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+pub struct Balance {
+    /// Only negative if a deposit dispute was allowed to drive it negative,
+    /// see `Policy::allow_negative_balance()`
+    #[serde(with = "crate::cents::signed::raw")]
+    available: SignedCents,
+    #[serde(with = "crate::cents::raw")]
     held: Cents,
-    frozen: bool,
-    transactions: HashMap<TransactionId, DisputableTransaction>,
-    disputes: HashSet<TransactionId>,
-    chargebacks: HashSet<TransactionId>,
 }
 
-impl Client {
+impl Balance {
     pub fn new() -> Self {
         Self {
-            available: Cents::new(0),
+            available: SignedCents::ZERO,
             held: Cents::new(0),
-            frozen: false,
-            transactions: HashMap::new(),
-            disputes: HashSet::new(),
-            chargebacks: HashSet::new(),
         }
     }
 
-    pub fn available(&self) -> Cents {
+    pub fn available(&self) -> SignedCents {
         self.available
     }
 
@@ -43,139 +48,753 @@ impl Client {
         self.held
     }
 
-    pub fn total(&self) -> Cents {
-        (self.available + self.held).unwrap()
+    /// Sum of available and held funds. Widened so that it cannot overflow, although
+    /// a `Client` rejects transactions that would take the total past `Cents::MAX`
+    pub fn total(&self) -> i128 {
+        self.available.value() + i128::from(self.held.value())
+    }
+
+    /// A balance of the given funds, if its total does not overflow
+    fn checked(available: SignedCents, held: Cents) -> Result<Self, TransactionError> {
+        credited_available(available, held)?;
+        Ok(Self { available, held })
+    }
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One client's side of a transfer, validated against the client but not yet applied
+#[derive(Debug)]
+pub(crate) struct TransferLeg {
+    id: TransactionId,
+    tx: DisputableTransaction,
+    balance: Balance,
+}
+
+/// Whether an account can transact
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum AccountStatus {
+    Active,
+    /// Frozen, either by a chargeback or a `lock` transaction. Cannot withdraw
+    Locked,
+    /// Rejects every further transaction
+    Closed,
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Active => write!(f, "active"),
+            Self::Locked => write!(f, "locked"),
+            Self::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+/// A client's accounts. Outside of the engine, only a read-only view, see `Engine::client()`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Client {
+    balances: BTreeMap<AssetId, Balance>,
+    status: AccountStatus,
+    transactions: HashMap<TransactionId, DisputableTransaction>,
+    authorizations: HashMap<TransactionId, Authorization>,
+    /// Number of transactions applied, for expiring authorizations
+    seq: u64,
+    /// Timestamp of the latest timestamped transaction, which later ones may not precede
+    last_timestamp: Option<Timestamp>,
+}
+
+impl Client {
+    pub(crate) fn new() -> Self {
+        Self {
+            balances: BTreeMap::new(),
+            status: AccountStatus::Active,
+            transactions: HashMap::new(),
+            authorizations: HashMap::new(),
+            seq: 0,
+            last_timestamp: None,
+        }
     }
 
-    pub fn frozen(&self) -> bool {
-        self.frozen
+    /// Balance in `asset`, zero if the client never transacted in it
+    pub fn balance(&self, asset: &str) -> Balance {
+        self.balances.get(asset).copied().unwrap_or_default()
     }
 
-    pub fn handle_tx(&mut self, tx: Transaction) -> Result<(), TransactionError> {
-        match tx.transaction_type {
-            TransactionType::Deposit => self.handle_deposit(tx),
-            TransactionType::Withdrawal => self.handle_withdrawal(tx),
-            TransactionType::Dispute => self.handle_dispute(tx),
-            TransactionType::Resolve => self.handle_resolve(tx),
-            TransactionType::Chargeback => self.handle_chargeback(tx),
+    /// Balances in every asset the client has transacted in, ordered by asset.
+    /// A client that has no balances yet has a zero balance in the default asset
+    pub fn balances(&self) -> Vec<(&str, Balance)> {
+        if self.balances.is_empty() {
+            return vec![("", Balance::new())];
         }
+        self.balances
+            .iter()
+            .map(|(asset, balance)| (asset.as_str(), *balance))
+            .collect()
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
     }
 
-    fn handle_deposit(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+    fn frozen(&self) -> bool {
+        self.status == AccountStatus::Locked
+    }
+
+    pub(crate) fn handle_tx(
+        &mut self,
+        tx: Transaction,
+        policy: &dyn Policy,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        self.begin_tx(tx.timestamp, policy)?;
+        let timestamp = tx.timestamp;
+        let result = match tx.transaction_type {
+            TransactionType::Deposit => self.handle_deposit(tx, policy),
+            TransactionType::Withdrawal => self.handle_withdrawal(tx, precision),
+            TransactionType::Dispute => self.handle_dispute(tx, policy, precision),
+            TransactionType::Resolve => self.handle_resolve(tx, precision),
+            TransactionType::Chargeback => self.handle_chargeback(tx, policy, precision),
+            TransactionType::Transfer => {
+                unreachable!("transfers span two clients and are applied by the engine")
+            }
+            TransactionType::Lock => self.handle_lock(),
+            TransactionType::Unlock => self.handle_unlock(),
+            TransactionType::Close => self.handle_close(precision),
+            TransactionType::Fee => self.handle_fee(tx, precision),
+            TransactionType::Adjustment => self.handle_adjustment(tx, precision),
+            TransactionType::Reversal => self.handle_reversal(tx, precision),
+            TransactionType::Authorize => self.handle_authorize(tx, precision),
+            TransactionType::Capture => self.handle_capture(tx, precision),
+            TransactionType::Void => self.handle_void(tx),
+        };
+        // the clock and transaction count only advance with applied transactions
+        if result.is_ok() {
+            self.seq += 1;
+            if timestamp.is_some() {
+                self.last_timestamp = timestamp;
+            }
+        }
+        result
+    }
+
+    /// Checks that the client may handle a transaction at `timestamp` and expires the
+    /// authorizations that the transaction would take past their window. Those stay
+    /// expired even if the transaction is then rejected, as they can no longer be captured
+    pub(crate) fn begin_tx(
+        &mut self,
+        timestamp: Option<Timestamp>,
+        policy: &dyn Policy,
+    ) -> Result<(), TransactionError> {
+        self.check_open()?;
+        self.check_timestamp(timestamp)?;
+        if let Some(expiry) = policy.authorization_expiry() {
+            self.expire_authorizations(expiry);
+        }
+        Ok(())
+    }
+
+    fn handle_deposit(
+        &mut self,
+        tx: Transaction,
+        policy: &dyn Policy,
+    ) -> Result<(), TransactionError> {
+        if self.frozen() && !policy.frozen_may_deposit() {
+            return Err(TransactionError::AccountFrozen);
+        }
         let id = tx.tx;
         let tx: DisputableTransaction = tx.try_into()?;
-        let amount = tx.amount;
+        let asset = tx.asset.clone();
+        let balance = self.balance(&asset);
+        let new_balance = Balance::checked(
+            credited_available(balance.available, tx.amount)?,
+            balance.held,
+        )?;
         // update below must be atomic
         self.insert_disputable_tx(id, tx)?;
-        // unwrap safety: panics if sum overflows
-        self.available = (self.available + amount).unwrap();
+        *self.balance_mut(asset) = new_balance;
         Ok(())
     }
 
-    fn handle_withdrawal(&mut self, tx: Transaction) -> Result<(), TransactionError> {
-        if self.frozen {
+    fn handle_withdrawal(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        if self.frozen() {
             return Err(TransactionError::AccountFrozen);
         }
         let id = tx.tx;
         let tx: DisputableTransaction = tx.try_into()?;
-        let new_available = self.available_after_debit(tx.amount)?;
+        let asset = tx.asset.clone();
+        let new_available = self.available_after_debit(&asset, tx.amount, precision)?;
+        // update below must be atomic
+        self.insert_disputable_tx(id, tx)?;
+        self.balance_mut(asset).available = new_available;
+        Ok(())
+    }
+
+    /// Fees are charged even on frozen accounts, but never beyond the available funds
+    fn handle_fee(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        let id = tx.tx;
+        let tx: DisputableTransaction = tx.try_into()?;
+        let asset = tx.asset.clone();
+        let new_available = self.available_after_debit(&asset, tx.amount, precision)?;
+        // update below must be atomic
+        self.insert_disputable_tx(id, tx)?;
+        self.balance_mut(asset).available = new_available;
+        Ok(())
+    }
+
+    /// Adjustments apply even on frozen accounts, but never beyond the available funds
+    fn handle_adjustment(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        let id = tx.tx;
+        let amount = tx.signed_amount().ok_or(TransactionError::NoAmount)?;
+        let tx: DisputableTransaction = tx.try_into()?;
+        let asset = tx.asset.clone();
+        let new_available = self.available_after(&asset, amount, precision)?;
         // update below must be atomic
         self.insert_disputable_tx(id, tx)?;
-        self.available = new_available;
+        self.balance_mut(asset).available = new_available;
+        Ok(())
+    }
+
+    /// Undoes a settled transaction's effect on the available funds. Transfers cannot be
+    /// reversed, as that would take funds back from the other client
+    fn handle_reversal(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        let id = tx.tx;
+        let reversed = self.get_disputable_transaction(id)?;
+        match reversed.state() {
+            DisputeState::Settled | DisputeState::Resolved => {}
+            DisputeState::Disputed => return Err(TransactionError::TransactionUnderDispute(id)),
+            DisputeState::ChargedBack => return Err(TransactionError::AlreadyChargedBack(id)),
+            DisputeState::Reversed => return Err(TransactionError::AlreadyReversed(id)),
+        }
+        let original = match reversed.transaction_type {
+            DisputableTransactionType::Deposit
+            | DisputableTransactionType::Adjustment { debit: false } => {
+                SignedCents::credit(reversed.amount)
+            }
+            DisputableTransactionType::Withdrawal
+            | DisputableTransactionType::Fee
+            | DisputableTransactionType::Adjustment { debit: true } => {
+                SignedCents::debit(reversed.amount)
+            }
+            DisputableTransactionType::TransferIn { .. }
+            | DisputableTransactionType::TransferOut { .. } => {
+                return Err(TransactionError::TransactionIrreversible(id))
+            }
+        };
+        let asset = reversed.asset.clone();
+        let new_available = self.available_after(&asset, original.negate(), precision)?;
+        let balance = Balance::checked(new_available, self.balance(&asset).held)?;
+        *self.balance_mut(asset) = balance;
+        self.disputed_mut(id).transition(DisputeState::Reversed);
         Ok(())
     }
 
-    fn handle_dispute(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+    /// Moves funds from available to held, like a withdrawal that has not happened yet
+    fn handle_authorize(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        if self.frozen() {
+            return Err(TransactionError::AccountFrozen);
+        }
+        let amount = tx.amount.ok_or(TransactionError::NoAmount)?;
+        let new_available = self.available_after_debit(&tx.asset, amount, precision)?;
+        let new_held = credited(self.balance(&tx.asset).held, amount)?;
+        let new_balance = Balance::checked(new_available, new_held)?;
+        self.check_new_tx(tx.tx)?;
+        // update below must be atomic
+        *self.balance_mut(tx.asset.clone()) = new_balance;
+        self.authorizations
+            .insert(tx.tx, Authorization::new(amount, tx.asset, self.seq + 1));
+        Ok(())
+    }
+
+    /// Debits held funds of an authorization, all that remain if no amount is given.
+    /// The authorization stays open until it is fully captured or voided
+    fn handle_capture(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
+        let id = tx.tx;
+        let auth = self.open_authorization(id)?;
+        let remaining = auth.remaining();
+        if tx.amount == Some(Cents::ZERO) {
+            return Err(TransactionError::NoAmount);
+        }
+        let amount = tx.amount.unwrap_or(remaining);
+        if amount > remaining {
+            return Err(TransactionError::ExceedsAuthorizedAmount {
+                tx: id,
+                requested: precision.amount(amount),
+                remaining: precision.amount(remaining),
+            });
+        }
+        let balance = self.balance_mut(auth.asset.clone());
+        // unwrap safety: panics if insufficient balance in held, i.e. the engine messed up
+        balance.held = (balance.held - amount).unwrap();
+        self.authorization_mut(id).capture(amount);
+        Ok(())
+    }
+
+    fn handle_void(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+        self.open_authorization(tx.tx)?;
+        self.release_authorization(tx.tx, AuthorizationState::Voided);
+        Ok(())
+    }
+
+    /// Releases the holds of authorizations that the client's next transaction would take
+    /// past `expiry` transactions
+    fn expire_authorizations(&mut self, expiry: u64) {
+        let next = self.seq + 1;
+        let expired: Vec<TransactionId> = self
+            .authorizations
+            .iter()
+            .filter(|(_, auth)| auth.expired(next, expiry))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.release_authorization(id, AuthorizationState::Expired);
+        }
+    }
+
+    /// Returns the rest of an open authorization's held funds to available
+    fn release_authorization(&mut self, id: TransactionId, state: AuthorizationState) {
+        let auth = self.authorization_mut(id);
+        let remaining = auth.remaining();
+        auth.release(state);
+        let asset = auth.asset.clone();
+        let balance = self.balance_mut(asset);
+        // unwrap safety: the funds were moved from available to held, so the total is unchanged
+        balance.held = (balance.held - remaining).unwrap();
+        balance.available = balance.available.checked_add(remaining).unwrap();
+    }
+
+    fn handle_dispute(
+        &mut self,
+        tx: Transaction,
+        policy: &dyn Policy,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
         let id = tx.tx;
         let disputed = self.get_disputable_transaction(id)?;
-        let amount = disputed.amount;
+        let amount = check_dispute(id, disputed, tx.amount, tx.timestamp, policy, precision)?;
+        let asset = disputed.asset.clone();
+        let timestamp = tx.timestamp;
         match disputed.transaction_type {
-            DisputableTransactionType::Deposit => self.handle_deposit_dispute(id, amount),
-            DisputableTransactionType::Withdrawal => self.handle_withdrawal_dispute(id, amount),
+            DisputableTransactionType::Deposit | DisputableTransactionType::TransferIn { .. } => {
+                if policy.allow_negative_balance() {
+                    return self
+                        .handle_deposit_dispute(id, asset, amount, timestamp, true, precision);
+                }
+                let amount = if policy.deposit_dispute_requires_available() {
+                    amount
+                } else {
+                    // negative available funds are never held
+                    let available = self.balance(&asset).available;
+                    amount.min(Cents::try_from(available).unwrap_or(Cents::ZERO))
+                };
+                self.handle_deposit_dispute(id, asset, amount, timestamp, false, precision)
+            }
+            DisputableTransactionType::Withdrawal | DisputableTransactionType::Fee => {
+                self.handle_withdrawal_dispute(id, asset, amount, timestamp)
+            }
+            DisputableTransactionType::TransferOut { .. } => {
+                unreachable!("transfers are disputed through the engine")
+            }
+            DisputableTransactionType::Adjustment { .. } => {
+                unreachable!("adjustments are never disputable")
+            }
         }
     }
 
     fn handle_deposit_dispute(
         &mut self,
         deposit_id: TransactionId,
+        asset: AssetId,
         amount: Cents,
+        timestamp: Option<Timestamp>,
+        allow_negative: bool,
+        precision: Precision,
     ) -> Result<(), TransactionError> {
-        let new_available = self.available_after_debit(amount)?;
-        self.insert_dispute(deposit_id)?;
-        self.available = new_available;
-        self.held = (self.held + amount).unwrap();
+        let new_available = if allow_negative {
+            self.balance(&asset)
+                .available
+                .checked_sub(amount)
+                .map_err(|_| TransactionError::Overflow)?
+        } else {
+            self.available_after_debit(&asset, amount, precision)?
+        };
+        let new_held = credited(self.balance(&asset).held, amount)?;
+        let new_balance = Balance::checked(new_available, new_held)?;
+        *self.balance_mut(asset) = new_balance;
+        self.disputed_mut(deposit_id).dispute(amount, timestamp);
         Ok(())
     }
 
     fn handle_withdrawal_dispute(
         &mut self,
         withdrawal_id: TransactionId,
+        asset: AssetId,
         amount: Cents,
+        timestamp: Option<Timestamp>,
     ) -> Result<(), TransactionError> {
-        self.insert_dispute(withdrawal_id)?;
-        self.held = (self.held + amount).unwrap();
+        let balance = self.balance(&asset);
+        // the withdrawn funds are held again, so the total grows
+        let new_balance = Balance::checked(balance.available, credited(balance.held, amount)?)?;
+        *self.balance_mut(asset) = new_balance;
+        self.disputed_mut(withdrawal_id).dispute(amount, timestamp);
         Ok(())
     }
 
-    fn handle_resolve(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+    fn handle_resolve(
+        &mut self,
+        tx: Transaction,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
         let id = tx.tx;
         let disputed = self.get_disputable_transaction(id)?;
-        let amount = disputed.amount;
-        if !self.disputes.contains(&id) {
-            return Err(TransactionError::TransactionNotUnderDispute(id));
-        }
+        let amount = check_settle(id, disputed, tx.amount, precision)?;
+        let asset = disputed.asset.clone();
         match disputed.transaction_type {
-            DisputableTransactionType::Deposit => self.handle_deposit_resolve(id, amount),
-            DisputableTransactionType::Withdrawal => self.handle_withdrawal_resolve(id, amount),
+            DisputableTransactionType::Deposit | DisputableTransactionType::TransferIn { .. } => {
+                self.handle_deposit_resolve(id, asset, amount)
+            }
+            DisputableTransactionType::Withdrawal | DisputableTransactionType::Fee => {
+                self.handle_withdrawal_resolve(id, asset, amount);
+                Ok(())
+            }
+            DisputableTransactionType::TransferOut { .. } => {
+                unreachable!("transfers are resolved through the engine")
+            }
+            DisputableTransactionType::Adjustment { .. } => {
+                unreachable!("adjustments are never disputed")
+            }
         }
-        Ok(())
     }
 
-    fn handle_deposit_resolve(&mut self, deposit_id: TransactionId, amount: Cents) {
+    fn handle_deposit_resolve(
+        &mut self,
+        deposit_id: TransactionId,
+        asset: AssetId,
+        amount: Cents,
+    ) -> Result<(), TransactionError> {
+        let balance = self.balance(&asset);
         // unwrap safety: panics if insufficient balance in held, i.e. the engine messed up
-        self.held = (self.held - amount).unwrap();
-        self.available = (self.available + amount).unwrap();
-        self.disputes.remove(&deposit_id);
+        let new_held = (balance.held - amount).unwrap();
+        let new_balance =
+            Balance::checked(credited_available(balance.available, amount)?, new_held)?;
+        *self.balance_mut(asset) = new_balance;
+        self.disputed_mut(deposit_id).settle(amount, false);
+        Ok(())
     }
 
-    fn handle_withdrawal_resolve(&mut self, withdrawal_id: TransactionId, amount: Cents) {
+    fn handle_withdrawal_resolve(
+        &mut self,
+        withdrawal_id: TransactionId,
+        asset: AssetId,
+        amount: Cents,
+    ) {
+        let balance = self.balance_mut(asset);
         // unwrap safety: panics if insufficient balance in held, i.e. the engine messed up
-        self.held = (self.held - amount).unwrap();
-        self.disputes.remove(&withdrawal_id);
+        balance.held = (balance.held - amount).unwrap();
+        self.disputed_mut(withdrawal_id).settle(amount, false);
     }
 
-    fn handle_chargeback(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+    fn handle_chargeback(
+        &mut self,
+        tx: Transaction,
+        policy: &dyn Policy,
+        precision: Precision,
+    ) -> Result<(), TransactionError> {
         let id = tx.tx;
         let disputed = self.get_disputable_transaction(id)?;
-        let amount = disputed.amount;
-        if !self.disputes.contains(&id) {
-            return Err(TransactionError::TransactionNotUnderDispute(id));
-        }
+        let amount = check_settle(id, disputed, tx.amount, precision)?;
+        let asset = disputed.asset.clone();
         match disputed.transaction_type {
-            DisputableTransactionType::Deposit => self.handle_deposit_chargeback(id, amount),
-            DisputableTransactionType::Withdrawal => self.handle_withdrawal_chargeback(id, amount),
+            DisputableTransactionType::Deposit | DisputableTransactionType::TransferIn { .. } => {
+                self.handle_deposit_chargeback(id, asset, amount)?
+            }
+            DisputableTransactionType::Withdrawal | DisputableTransactionType::Fee => {
+                self.handle_withdrawal_chargeback(id, asset, amount)?
+            }
+            DisputableTransactionType::TransferOut { .. } => {
+                unreachable!("transfers are charged back through the engine")
+            }
+            DisputableTransactionType::Adjustment { .. } => {
+                unreachable!("adjustments are never disputed")
+            }
+        }
+        if policy.chargeback_freezes() {
+            self.status = AccountStatus::Locked;
         }
-        self.frozen = true;
         Ok(())
     }
 
-    fn handle_deposit_chargeback(&mut self, deposit_id: TransactionId, amount: Cents) {
-        // Frozen accounts are not allowed to withdraw via deposit dispute chargeback
-        if self.frozen {
-            return;
+    fn handle_deposit_chargeback(
+        &mut self,
+        deposit_id: TransactionId,
+        asset: AssetId,
+        amount: Cents,
+    ) -> Result<(), TransactionError> {
+        // Frozen accounts are not allowed to withdraw via deposit dispute chargeback, unless
+        // the freeze is the result of an earlier partial chargeback of this same dispute,
+        // as a transaction that was partly charged back can never be disputed again
+        let partly_charged_back = self.transactions[&deposit_id].charged_back != Cents::ZERO;
+        if self.frozen() && !partly_charged_back {
+            return Err(TransactionError::AccountFrozen);
         }
+        let balance = self.balance_mut(asset);
         // unwrap safety: panics if insufficient balance in held, i.e. the engine messed up
-        self.held = (self.held - amount).unwrap();
-        self.disputes.remove(&deposit_id);
-        self.chargebacks.insert(deposit_id);
+        balance.held = (balance.held - amount).unwrap();
+        self.disputed_mut(deposit_id).settle(amount, true);
+        Ok(())
     }
 
-    fn handle_withdrawal_chargeback(&mut self, withdrawal_id: TransactionId, amount: Cents) {
-        self.available = (self.available + amount).unwrap();
+    fn handle_withdrawal_chargeback(
+        &mut self,
+        withdrawal_id: TransactionId,
+        asset: AssetId,
+        amount: Cents,
+    ) -> Result<(), TransactionError> {
+        let balance = self.balance(&asset);
         // unwrap safety: panics if insufficient balance in held, i.e. the engine messed up
-        self.held = (self.held - amount).unwrap();
-        self.disputes.remove(&withdrawal_id);
-        self.chargebacks.insert(withdrawal_id);
+        let new_held = (balance.held - amount).unwrap();
+        let new_balance =
+            Balance::checked(credited_available(balance.available, amount)?, new_held)?;
+        *self.balance_mut(asset) = new_balance;
+        self.disputed_mut(withdrawal_id).settle(amount, true);
+        Ok(())
+    }
+
+    /// IDs of every transaction and authorization the client has a record of
+    pub(crate) fn tx_ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.transactions
+            .keys()
+            .chain(self.authorizations.keys())
+            .copied()
+    }
+
+    /// Whether the client has a record of transaction or authorization `id`
+    pub(crate) fn has_tx(&self, id: TransactionId) -> bool {
+        self.transactions.contains_key(&id) || self.authorizations.contains_key(&id)
+    }
+
+    /// Disputable transaction `id`, if the client has one
+    pub fn transaction(&self, id: TransactionId) -> Option<&DisputableTransaction> {
+        self.transactions.get(&id)
+    }
+
+    /// Asset of the client's transaction or authorization `id`, if it has either
+    pub(crate) fn record_asset(&self, id: TransactionId) -> Option<&str> {
+        self.transactions
+            .get(&id)
+            .map(|tx| &tx.asset)
+            .or_else(|| self.authorizations.get(&id).map(|auth| &auth.asset))
+            .map(String::as_str)
+    }
+
+    /// Number of the client's transactions in `asset` that are in `state`,
+    /// e.g. open disputes or charged back transactions
+    pub(crate) fn count_in_state(&self, asset: &str, state: DisputeState) -> usize {
+        self.transactions
+            .values()
+            .filter(|tx| tx.asset == asset && tx.state() == state)
+            .count()
+    }
+
+    /// Validates, but does not apply, the source's side of a transfer to client `to`
+    pub(crate) fn prepare_transfer_out(
+        &self,
+        tx: &Transaction,
+        to: ClientId,
+        precision: Precision,
+    ) -> Result<TransferLeg, TransactionError> {
+        self.check_open()?;
+        self.check_timestamp(tx.timestamp)?;
+        if self.frozen() {
+            return Err(TransactionError::AccountFrozen);
+        }
+        let amount = tx.amount.ok_or(TransactionError::NoAmount)?;
+        let available = self.available_after_debit(&tx.asset, amount, precision)?;
+        self.check_new_tx(tx.tx)?;
+        Ok(TransferLeg {
+            id: tx.tx,
+            balance: Balance::checked(available, self.balance(&tx.asset).held)?,
+            tx: DisputableTransaction::new(
+                DisputableTransactionType::TransferOut { to },
+                amount,
+                tx.asset.clone(),
+                tx.timestamp,
+            ),
+        })
+    }
+
+    /// Validates, but does not apply, the destination's side of a transfer from `tx.client`
+    pub(crate) fn prepare_transfer_in(
+        &self,
+        tx: &Transaction,
+        policy: &dyn Policy,
+    ) -> Result<TransferLeg, TransactionError> {
+        self.check_open()?;
+        self.check_timestamp(tx.timestamp)?;
+        if self.frozen() && !policy.frozen_may_deposit() {
+            return Err(TransactionError::AccountFrozen);
+        }
+        let amount = tx.amount.ok_or(TransactionError::NoAmount)?;
+        let balance = self.balance(&tx.asset);
+        self.check_new_tx(tx.tx)?;
+        Ok(TransferLeg {
+            id: tx.tx,
+            balance: Balance::checked(
+                credited_available(balance.available, amount)?,
+                balance.held,
+            )?,
+            tx: DisputableTransaction::new(
+                DisputableTransactionType::TransferIn { from: tx.client },
+                amount,
+                tx.asset.clone(),
+                tx.timestamp,
+            ),
+        })
+    }
+
+    pub(crate) fn apply_transfer(&mut self, leg: TransferLeg) {
+        self.seq += 1;
+        if leg.tx.timestamp.is_some() {
+            self.last_timestamp = leg.tx.timestamp;
+        }
+        *self.balance_mut(leg.tx.asset.clone()) = leg.balance;
+        self.transactions.insert(leg.id, leg.tx);
+    }
+
+    /// Checks that `amount` can be credited to the available funds in `asset`
+    pub(crate) fn check_credit(&self, asset: &str, amount: Cents) -> Result<(), TransactionError> {
+        self.check_open()?;
+        let balance = self.balance(asset);
+        Balance::checked(credited_available(balance.available, amount)?, balance.held)?;
+        Ok(())
+    }
+
+    /// Credits the available funds in `asset`, e.g. when a transfer out is charged back
+    pub(crate) fn credit(&mut self, asset: &str, amount: Cents) -> Result<(), TransactionError> {
+        self.check_credit(asset, amount)?;
+        let balance = self.balance_mut(asset.to_owned());
+        // unwrap safety: checked above
+        balance.available = balance.available.checked_add(amount).unwrap();
+        Ok(())
+    }
+
+    /// Moves the source's side of transfer `id` to the dispute state of the destination's side
+    pub(crate) fn sync_transfer_out(
+        &mut self,
+        id: TransactionId,
+        state: DisputeState,
+        disputed_at: Option<Timestamp>,
+    ) {
+        // unwrap safety: panics if the transfer does not exist, i.e. the engine messed up
+        let tx = self.transactions.get_mut(&id).unwrap();
+        if tx.state() != state {
+            tx.transition(state);
+        }
+        tx.disputed_at = disputed_at;
+    }
+
+    /// Timestamp of the latest applied timestamped transaction
+    pub(crate) fn last_timestamp(&self) -> Option<Timestamp> {
+        self.last_timestamp
+    }
+
+    /// Transactions that have been under dispute for longer than `period` at `now`,
+    /// in order of their IDs
+    pub(crate) fn overdue_disputes(&self, now: Timestamp, period: u64) -> Vec<TransactionId> {
+        let mut overdue: Vec<TransactionId> = self
+            .transactions
+            .iter()
+            .filter(|(_, tx)| tx.dispute_overdue(now, period))
+            .map(|(id, _)| *id)
+            .collect();
+        overdue.sort_unstable();
+        overdue
+    }
+
+    /// Checks that a transaction at `timestamp` does not precede the client's latest one
+    fn check_timestamp(&self, timestamp: Option<Timestamp>) -> Result<(), TransactionError> {
+        match (timestamp, self.last_timestamp) {
+            (Some(timestamp), Some(last)) if timestamp < last => {
+                Err(TransactionError::TimestampOutOfOrder { timestamp, last })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_open(&self) -> Result<(), TransactionError> {
+        if self.status == AccountStatus::Closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        Ok(())
+    }
+
+    fn check_new_tx(&self, id: TransactionId) -> Result<(), TransactionError> {
+        if self.has_tx(id) {
+            return Err(TransactionError::DuplicateTransaction(id));
+        }
+        Ok(())
+    }
+
+    fn handle_lock(&mut self) -> Result<(), TransactionError> {
+        if self.status == AccountStatus::Locked {
+            return Err(TransactionError::AccountFrozen);
+        }
+        self.status = AccountStatus::Locked;
+        Ok(())
+    }
+
+    fn handle_unlock(&mut self) -> Result<(), TransactionError> {
+        if self.status != AccountStatus::Locked {
+            return Err(TransactionError::AccountNotLocked);
+        }
+        self.status = AccountStatus::Active;
+        Ok(())
+    }
+
+    /// Accounts can only be closed once no funds are held for disputes
+    fn handle_close(&mut self, precision: Precision) -> Result<(), TransactionError> {
+        if let Some((asset, balance)) = self
+            .balances
+            .iter()
+            .find(|(_, balance)| balance.held != Cents::ZERO)
+        {
+            return Err(TransactionError::HeldFundsOnClose {
+                asset: asset.clone(),
+                held: precision.amount(balance.held),
+            });
+        }
+        self.status = AccountStatus::Closed;
+        Ok(())
     }
 
     fn insert_disputable_tx(
@@ -183,30 +802,72 @@ impl Client {
         id: TransactionId,
         tx: DisputableTransaction,
     ) -> Result<(), TransactionError> {
-        if self.transactions.contains_key(&id) {
-            return Err(TransactionError::DuplicateTransaction(id));
-        }
+        self.check_new_tx(id)?;
         self.transactions.insert(id, tx);
         Ok(())
     }
 
-    fn insert_dispute(&mut self, id: TransactionId) -> Result<(), TransactionError> {
-        if self.disputes.contains(&id) {
-            return Err(TransactionError::DuplicateTransaction(id));
+    /// A transaction that was checked to exist
+    fn disputed_mut(&mut self, id: TransactionId) -> &mut DisputableTransaction {
+        // unwrap safety: panics if the transaction does not exist, i.e. the engine messed up
+        self.transactions.get_mut(&id).unwrap()
+    }
+
+    /// Authorization `id`, if it exists and still holds funds
+    fn open_authorization(&self, id: TransactionId) -> Result<&Authorization, TransactionError> {
+        let auth = self
+            .authorizations
+            .get(&id)
+            .ok_or(TransactionError::UnknownAuthorization(id))?;
+        match auth.state() {
+            AuthorizationState::Open => Ok(auth),
+            AuthorizationState::Captured => Err(TransactionError::AuthorizationCaptured(id)),
+            AuthorizationState::Voided => Err(TransactionError::AuthorizationVoided(id)),
+            AuthorizationState::Expired => Err(TransactionError::AuthorizationExpired(id)),
         }
-        if self.chargebacks.contains(&id) {
-            return Err(TransactionError::AlreadyChargedBack(id));
+    }
+
+    /// An authorization that was checked to exist
+    fn authorization_mut(&mut self, id: TransactionId) -> &mut Authorization {
+        // unwrap safety: panics if the authorization does not exist, i.e. the engine messed up
+        self.authorizations.get_mut(&id).unwrap()
+    }
+
+    fn balance_mut(&mut self, asset: AssetId) -> &mut Balance {
+        self.balances.entry(asset).or_default()
+    }
+
+    /// Calculates, but does not update, the available balance in `asset` after a debit
+    /// which may not drive it negative
+    fn available_after_debit(
+        &self,
+        asset: &str,
+        debit: Cents,
+        precision: Precision,
+    ) -> Result<SignedCents, TransactionError> {
+        let available = self.balance(asset).available;
+        match available.checked_sub(debit) {
+            Ok(new_available) if !new_available.is_negative() => Ok(new_available),
+            _ => Err(TransactionError::InsufficientBalance {
+                available: precision.amount(available),
+                requested: precision.amount(debit),
+            }),
         }
-        self.disputes.insert(id);
-        Ok(())
     }
 
-    /// Calculates, but does not update, the available balance after a debit
-    fn available_after_debit(&self, debit: Cents) -> Result<Cents, TransactionError> {
-        (self.available - debit).ok_or_else(|| TransactionError::InsufficientBalance {
-            available: self.available,
-            requested: debit,
-        })
+    /// Calculates, but does not update, the available balance in `asset` after a signed
+    /// credit or debit
+    fn available_after(
+        &self,
+        asset: &str,
+        amount: SignedCents,
+        precision: Precision,
+    ) -> Result<SignedCents, TransactionError> {
+        if amount.is_negative() {
+            self.available_after_debit(asset, amount.magnitude(), precision)
+        } else {
+            credited_available(self.balance(asset).available, amount.magnitude())
+        }
     }
 
     fn get_disputable_transaction(
@@ -215,6 +876,114 @@ impl Client {
     ) -> Result<&DisputableTransaction, TransactionError> {
         self.transactions
             .get(&id)
-            .ok_or_else(|| TransactionError::TransactionDoesNotExist(id))
+            .ok_or(TransactionError::TransactionDoesNotExist(id))
+    }
+}
+
+/// `amount + credit`, rejecting the transaction if it overflows
+fn credited(amount: Cents, credit: Cents) -> Result<Cents, TransactionError> {
+    (amount + credit).map_err(|_| TransactionError::Overflow)
+}
+
+/// Like `credited()`, for the available funds, which may be negative
+fn credited_available(
+    available: SignedCents,
+    credit: Cents,
+) -> Result<SignedCents, TransactionError> {
+    available
+        .checked_add(credit)
+        .map_err(|_| TransactionError::Overflow)
+}
+
+/// Checks that a transaction may be disputed at `timestamp` under the policy
+/// and returns the amount to dispute, all of the undisputed amount if none was requested
+fn check_dispute(
+    id: TransactionId,
+    tx: &DisputableTransaction,
+    requested: Option<Cents>,
+    timestamp: Option<Timestamp>,
+    policy: &dyn Policy,
+    precision: Precision,
+) -> Result<Cents, TransactionError> {
+    match tx.transaction_type {
+        DisputableTransactionType::Withdrawal if !policy.withdrawals_disputable() => {
+            return Err(TransactionError::TransactionIndisputable(
+                TransactionType::Withdrawal,
+            ));
+        }
+        DisputableTransactionType::Fee if !policy.fees_disputable() => {
+            return Err(TransactionError::TransactionIndisputable(
+                TransactionType::Fee,
+            ));
+        }
+        DisputableTransactionType::Adjustment { .. } => {
+            return Err(TransactionError::TransactionIndisputable(
+                TransactionType::Adjustment,
+            ));
+        }
+        _ => {}
+    }
+    match tx.state() {
+        // a transaction under dispute can only have more of its amount disputed
+        DisputeState::Disputed if requested.is_none() => {
+            return Err(TransactionError::DuplicateTransaction(id))
+        }
+        DisputeState::Disputed => {}
+        DisputeState::ChargedBack => return Err(TransactionError::AlreadyChargedBack(id)),
+        DisputeState::Reversed => return Err(TransactionError::AlreadyReversed(id)),
+        DisputeState::Settled | DisputeState::Resolved => match policy.max_disputes() {
+            Some(max_disputes) if tx.dispute_count() >= max_disputes => {
+                return Err(TransactionError::DisputeLimitReached {
+                    tx: id,
+                    max_disputes,
+                });
+            }
+            _ => {}
+        },
+    }
+    if let (Some(window), Some(at), Some(disputed)) =
+        (policy.dispute_window(), tx.timestamp, timestamp)
+    {
+        if disputed.saturating_sub(at) > window {
+            return Err(TransactionError::DisputeWindowExpired { tx: id, window });
+        }
+    }
+    let undisputed = tx.undisputed();
+    if requested == Some(Cents::ZERO) {
+        return Err(TransactionError::NoAmount);
+    }
+    let amount = requested.unwrap_or(undisputed);
+    if amount > undisputed {
+        return Err(TransactionError::DisputeExceedsAmount {
+            tx: id,
+            requested: precision.amount(amount),
+            undisputed: precision.amount(undisputed),
+        });
+    }
+    Ok(amount)
+}
+
+/// Checks that a transaction is under dispute and returns the amount to resolve or
+/// charge back, all of the held amount if none was requested
+fn check_settle(
+    id: TransactionId,
+    tx: &DisputableTransaction,
+    requested: Option<Cents>,
+    precision: Precision,
+) -> Result<Cents, TransactionError> {
+    if tx.state() != DisputeState::Disputed {
+        return Err(TransactionError::TransactionNotUnderDispute(id));
+    }
+    if requested == Some(Cents::ZERO) {
+        return Err(TransactionError::NoAmount);
+    }
+    let amount = requested.unwrap_or(tx.held);
+    if amount > tx.held {
+        return Err(TransactionError::ExceedsDisputedAmount {
+            tx: id,
+            requested: precision.amount(amount),
+            disputed: precision.amount(tx.held),
+        });
     }
+    Ok(amount)
 }
@@ -1,5 +1,7 @@
-use crate::cents::Cents;
-use crate::transaction::{Transaction, TransactionType};
+use crate::cents::{Cents, Precision, SignedCents};
+use crate::err::TransactionError;
+use crate::policy::DefaultPolicy;
+use crate::transaction::{tx, Transaction, TransactionType};
 
 use super::*;
 
@@ -11,11 +13,14 @@ fn run_transactions(
     expected_frozen: bool,
 ) {
     for tx in txs {
-        let _ = client.handle_tx(tx);
+        let _ = client.handle_tx(tx, &DefaultPolicy::default(), Precision::default());
     }
-    assert_eq!(expected_avail, client.available);
-    assert_eq!(expected_held, client.held);
-    assert_eq!(expected_frozen, client.frozen);
+    assert_eq!(
+        SignedCents::from(expected_avail),
+        client.balance("").available()
+    );
+    assert_eq!(expected_held, client.balance("").held());
+    assert_eq!(expected_frozen, client.frozen());
 }
 
 #[test]
@@ -24,18 +29,8 @@ fn basic() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 0,
-                amount: Some(Cents::new(8)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(7)),
-            },
+            tx(TransactionType::Deposit, 0, 0, Some(8)),
+            tx(TransactionType::Withdrawal, 0, 1, Some(7)),
         ],
         Cents::new(1),
         Cents::new(0),
@@ -49,24 +44,9 @@ fn resolve() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(72)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Resolve,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
+            tx(TransactionType::Deposit, 0, 1, Some(72)),
+            tx(TransactionType::Dispute, 0, 1, None),
+            tx(TransactionType::Resolve, 0, 1, None),
         ],
         Cents::new(72),
         Cents::new(0),
@@ -76,24 +56,9 @@ fn resolve() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 2,
-                amount: Some(Cents::new(70)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 2,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Resolve,
-                client: 0,
-                tx: 2,
-                amount: None,
-            },
+            tx(TransactionType::Withdrawal, 0, 2, Some(70)),
+            tx(TransactionType::Dispute, 0, 2, None),
+            tx(TransactionType::Resolve, 0, 2, None),
         ],
         Cents::new(2),
         Cents::new(0),
@@ -107,18 +72,8 @@ fn duplicate_tx_id() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 0,
-                amount: Some(Cents::new(34)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 0,
-                amount: Some(Cents::new(33)),
-            },
+            tx(TransactionType::Deposit, 0, 0, Some(34)),
+            tx(TransactionType::Withdrawal, 0, 0, Some(33)),
         ],
         Cents::new(34),
         Cents::new(0),
@@ -132,18 +87,8 @@ fn overdraft() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 0,
-                amount: Some(Cents::new(3536)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(3537)),
-            },
+            tx(TransactionType::Deposit, 0, 0, Some(3536)),
+            tx(TransactionType::Withdrawal, 0, 1, Some(3537)),
         ],
         Cents::new(3536),
         Cents::new(0),
@@ -157,49 +102,14 @@ fn no_withdrawal_after_frozen() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(3242)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Chargeback,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 2,
-                amount: Some(Cents::new(1)),
-            },
+            tx(TransactionType::Deposit, 0, 1, Some(3242)),
+            tx(TransactionType::Dispute, 0, 1, None),
+            tx(TransactionType::Chargeback, 0, 1, None),
+            tx(TransactionType::Withdrawal, 0, 2, Some(1)),
             // try to withdraw money via deposit dispute chargeback
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 3,
-                amount: Some(Cents::new(169)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 3,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Chargeback,
-                client: 0,
-                tx: 3,
-                amount: None,
-            },
+            tx(TransactionType::Deposit, 0, 3, Some(169)),
+            tx(TransactionType::Dispute, 0, 3, None),
+            tx(TransactionType::Chargeback, 0, 3, None),
         ],
         Cents::new(0),
         Cents::new(169),
@@ -213,42 +123,12 @@ fn no_chargeback_twice() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 435,
-                amount: Some(Cents::new(100)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(99)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Chargeback,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Chargeback,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
+            tx(TransactionType::Deposit, 0, 435, Some(100)),
+            tx(TransactionType::Withdrawal, 0, 1, Some(99)),
+            tx(TransactionType::Dispute, 0, 1, None),
+            tx(TransactionType::Chargeback, 0, 1, None),
+            tx(TransactionType::Dispute, 0, 1, None),
+            tx(TransactionType::Chargeback, 0, 1, None),
         ],
         Cents::new(100),
         Cents::new(0),
@@ -262,24 +142,9 @@ fn no_dispute_twice() {
     run_transactions(
         &mut client,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(69)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
+            tx(TransactionType::Deposit, 0, 1, Some(69)),
+            tx(TransactionType::Dispute, 0, 1, None),
+            tx(TransactionType::Dispute, 0, 1, None),
         ],
         Cents::new(0),
         Cents::new(69),
@@ -293,27 +158,903 @@ fn not_enough_to_dispute_deposit() {
     run_transactions(
         &mut client,
         vec![
+            tx(TransactionType::Deposit, 0, 1, Some(69)),
+            tx(TransactionType::Withdrawal, 0, 2, Some(1)),
+            tx(TransactionType::Dispute, 0, 1, None),
+        ],
+        Cents::new(68),
+        Cents::new(0),
+        false,
+    );
+}
+
+#[test]
+fn multiple_assets() {
+    let mut client = Client::new();
+    for tx in [
+        Transaction {
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Deposit, 0, 1, Some(100))
+        },
+        Transaction {
+            asset: "EUR".to_owned(),
+            ..tx(TransactionType::Deposit, 0, 2, Some(50))
+        },
+        // insufficient EUR even though there is enough USD
+        Transaction {
+            asset: "EUR".to_owned(),
+            ..tx(TransactionType::Withdrawal, 0, 3, Some(60))
+        },
+        Transaction {
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Withdrawal, 0, 4, Some(60))
+        },
+        // disputes apply to the asset of the disputed transaction
+        tx(TransactionType::Dispute, 0, 2, None),
+    ] {
+        let _ = client.handle_tx(tx, &DefaultPolicy::default(), Precision::default());
+    }
+    assert_eq!(SignedCents::new(40), client.balance("USD").available());
+    assert_eq!(Cents::new(0), client.balance("USD").held());
+    assert_eq!(SignedCents::new(0), client.balance("EUR").available());
+    assert_eq!(Cents::new(50), client.balance("EUR").held());
+    assert_eq!(0, client.balance("").total());
+    let assets: Vec<&str> = client.balances().into_iter().map(|(a, _)| a).collect();
+    assert_eq!(vec!["EUR", "USD"], assets);
+}
+
+#[test]
+fn deposit_overflow() {
+    let mut client = Client::new();
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 1, Some(u64::MAX - 1)),
+            &DefaultPolicy::default(),
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::Overflow),
+        client.handle_tx(
+            tx(TransactionType::Deposit, 0, 2, Some(2)),
+            &DefaultPolicy::default(),
+            Precision::default()
+        )
+    );
+    // rejected atomically: tx 2 was not recorded, so it can be retried
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 2, Some(1)),
+            &DefaultPolicy::default(),
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        SignedCents::from(Cents::MAX),
+        client.balance("").available()
+    );
+}
+
+#[test]
+fn withdrawal_dispute_overflow() {
+    let mut client = Client::new();
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 1, Some(u64::MAX)),
+            &DefaultPolicy::default(),
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Withdrawal, 0, 2, Some(1)),
+            &DefaultPolicy::default(),
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 3, Some(1)),
+            &DefaultPolicy::default(),
+            Precision::default(),
+        )
+        .unwrap();
+    // holding the withdrawn funds again would take the total past the maximum
+    assert_eq!(
+        Err(TransactionError::Overflow),
+        client.handle_tx(
+            tx(TransactionType::Dispute, 0, 2, None),
+            &DefaultPolicy::default(),
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::TransactionNotUnderDispute(2)),
+        client.handle_tx(
+            tx(TransactionType::Resolve, 0, 2, None),
+            &DefaultPolicy::default(),
+            Precision::default()
+        )
+    );
+    assert_eq!(Cents::new(0), client.balance("").held());
+    assert_eq!(i128::from(u64::MAX), client.balance("").total());
+}
+
+fn dispute_cycle(
+    client: &mut Client,
+    id: u32,
+    policy: &dyn Policy,
+) -> Result<(), TransactionError> {
+    client.handle_tx(
+        tx(TransactionType::Dispute, 0, id, None),
+        policy,
+        Precision::default(),
+    )?;
+    client.handle_tx(
+        tx(TransactionType::Resolve, 0, id, None),
+        policy,
+        Precision::default(),
+    )
+}
+
+#[test]
+fn dispute_lifecycle() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 1, Some(10)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(DisputeState::Settled, client.transactions[&1].state());
+    dispute_cycle(&mut client, 1, &policy).unwrap();
+    assert_eq!(DisputeState::Resolved, client.transactions[&1].state());
+    // resolved transactions can be disputed again by default
+    client
+        .handle_tx(
+            tx(TransactionType::Dispute, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Chargeback, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    let disputed = &client.transactions[&1];
+    assert_eq!(DisputeState::ChargedBack, disputed.state());
+    assert_eq!(2, disputed.dispute_count());
+    assert_eq!(
+        Err(TransactionError::AlreadyChargedBack(1)),
+        client.handle_tx(
+            tx(TransactionType::Dispute, 0, 1, None),
+            &policy,
+            Precision::default()
+        )
+    );
+}
+
+#[test]
+fn dispute_limit() {
+    let policy = DefaultPolicy {
+        max_disputes: Some(2),
+        ..DefaultPolicy::default()
+    };
+    let mut client = Client::new();
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 1, Some(10)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    dispute_cycle(&mut client, 1, &policy).unwrap();
+    dispute_cycle(&mut client, 1, &policy).unwrap();
+    assert_eq!(
+        Err(TransactionError::DisputeLimitReached {
+            tx: 1,
+            max_disputes: 2
+        }),
+        dispute_cycle(&mut client, 1, &policy)
+    );
+    assert_eq!(DisputeState::Resolved, client.transactions[&1].state());
+    assert_eq!(SignedCents::new(10), client.balance("").available());
+}
+
+#[test]
+fn lock_unlock() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    let mut run = |tx| client.handle_tx(tx, &policy, Precision::default());
+    assert_eq!(
+        Err(TransactionError::AccountNotLocked),
+        run(tx(TransactionType::Unlock, 0, 0, None))
+    );
+    run(tx(TransactionType::Deposit, 0, 1, Some(10))).unwrap();
+    run(tx(TransactionType::Lock, 0, 0, None)).unwrap();
+    assert_eq!(
+        Err(TransactionError::AccountFrozen),
+        run(tx(TransactionType::Lock, 0, 0, None))
+    );
+    assert_eq!(
+        Err(TransactionError::AccountFrozen),
+        run(tx(TransactionType::Withdrawal, 0, 2, Some(1)))
+    );
+    run(tx(TransactionType::Unlock, 0, 0, None)).unwrap();
+    run(tx(TransactionType::Withdrawal, 0, 2, Some(1))).unwrap();
+    assert_eq!(AccountStatus::Active, client.status());
+    assert_eq!(SignedCents::new(9), client.balance("").available());
+}
+
+#[test]
+fn unlock_after_chargeback() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Deposit, 0, 2, Some(5)),
+        tx(TransactionType::Dispute, 0, 1, None),
+        tx(TransactionType::Chargeback, 0, 1, None),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(AccountStatus::Locked, client.status());
+    client
+        .handle_tx(
+            tx(TransactionType::Unlock, 0, 0, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Withdrawal, 0, 3, Some(5)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(SignedCents::new(0), client.balance("").available());
+}
+
+#[test]
+fn chargeback_after_lock() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Lock, 0, 0, None),
+        tx(TransactionType::Dispute, 0, 1, None),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    // rejected rather than silently leaving the funds held
+    assert_eq!(
+        Err(TransactionError::AccountFrozen),
+        client.handle_tx(
+            tx(TransactionType::Chargeback, 0, 1, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(Cents::new(10), client.balance("").held());
+    assert_eq!(DisputeState::Disputed, client.transactions[&1].state());
+    // once unlocked, the chargeback goes through and the account can be closed
+    for tx in [
+        tx(TransactionType::Unlock, 0, 0, None),
+        tx(TransactionType::Chargeback, 0, 1, None),
+        tx(TransactionType::Unlock, 0, 0, None),
+        tx(TransactionType::Close, 0, 0, None),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(Cents::new(0), client.balance("").held());
+    assert_eq!(AccountStatus::Closed, client.status());
+}
+
+#[test]
+fn close() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    client
+        .handle_tx(
             Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(69)),
+                asset: "USD".to_owned(),
+                ..tx(TransactionType::Deposit, 0, 1, Some(10))
             },
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Dispute, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::HeldFundsOnClose {
+            asset: "USD".to_owned(),
+            held: Precision::default().amount(10)
+        }),
+        client.handle_tx(
+            tx(TransactionType::Close, 0, 0, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    client
+        .handle_tx(
+            tx(TransactionType::Resolve, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Close, 0, 0, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(AccountStatus::Closed, client.status());
+    for tx in [
+        Transaction {
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Deposit, 0, 2, Some(1))
+        },
+        tx(TransactionType::Dispute, 0, 1, None),
+        tx(TransactionType::Unlock, 0, 0, None),
+        tx(TransactionType::Close, 0, 0, None),
+    ] {
+        assert_eq!(
+            Err(TransactionError::AccountClosed),
+            client.handle_tx(tx, &policy, Precision::default())
+        );
+    }
+    assert_eq!(SignedCents::new(10), client.balance("USD").available());
+}
+
+#[test]
+fn partial_disputes() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    let mut run = |tx| client.handle_tx(tx, &policy, Precision::default());
+    run(tx(TransactionType::Deposit, 0, 1, Some(10))).unwrap();
+    run(tx(TransactionType::Dispute, 0, 1, Some(3))).unwrap();
+    run(tx(TransactionType::Dispute, 0, 1, Some(4))).unwrap();
+    assert_eq!(
+        Err(TransactionError::DisputeExceedsAmount {
+            tx: 1,
+            requested: Precision::default().amount(4),
+            undisputed: Precision::default().amount(3)
+        }),
+        run(tx(TransactionType::Dispute, 0, 1, Some(4)))
+    );
+    assert_eq!(
+        Err(TransactionError::ExceedsDisputedAmount {
+            tx: 1,
+            requested: Precision::default().amount(8),
+            disputed: Precision::default().amount(7)
+        }),
+        run(tx(TransactionType::Resolve, 0, 1, Some(8)))
+    );
+    run(tx(TransactionType::Resolve, 0, 1, Some(2))).unwrap();
+    assert_eq!(SignedCents::new(5), client.balance("").available());
+    assert_eq!(Cents::new(5), client.balance("").held());
+    assert_eq!(DisputeState::Disputed, client.transactions[&1].state());
+    // the rest of the held funds are resolved without an amount
+    client
+        .handle_tx(
+            tx(TransactionType::Resolve, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(SignedCents::new(10), client.balance("").available());
+    assert_eq!(DisputeState::Resolved, client.transactions[&1].state());
+    assert_eq!(1, client.transactions[&1].dispute_count());
+}
+
+#[test]
+fn partial_chargeback() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Withdrawal, 0, 2, Some(6)),
+        tx(TransactionType::Dispute, 0, 2, Some(4)),
+        tx(TransactionType::Chargeback, 0, 2, Some(1)),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(SignedCents::new(5), client.balance("").available());
+    assert_eq!(Cents::new(3), client.balance("").held());
+    assert_eq!(AccountStatus::Locked, client.status());
+    client
+        .handle_tx(
+            tx(TransactionType::Resolve, 0, 2, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    let disputed = &client.transactions[&2];
+    // partly charged back, so it can never be disputed again
+    assert_eq!(DisputeState::ChargedBack, disputed.state());
+    assert_eq!(Cents::new(1), disputed.charged_back);
+    assert_eq!(
+        Err(TransactionError::AlreadyChargedBack(2)),
+        client.handle_tx(
+            tx(TransactionType::Dispute, 0, 2, Some(1)),
+            &policy,
+            Precision::default()
+        )
+    );
+}
+
+#[test]
+fn partial_deposit_chargeback() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Dispute, 0, 1, None),
+        tx(TransactionType::Chargeback, 0, 1, Some(4)),
+        // the first chargeback froze the account, which must not stop the rest of the dispute
+        tx(TransactionType::Chargeback, 0, 1, Some(6)),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(SignedCents::new(0), client.balance("").available());
+    assert_eq!(Cents::new(0), client.balance("").held());
+    assert_eq!(AccountStatus::Locked, client.status());
+    let disputed = &client.transactions[&1];
+    assert_eq!(DisputeState::ChargedBack, disputed.state());
+    assert_eq!(Cents::new(10), disputed.charged_back);
+    assert_eq!(
+        Err(TransactionError::TransactionNotUnderDispute(1)),
+        client.handle_tx(
+            tx(TransactionType::Resolve, 0, 1, Some(6)),
+            &policy,
+            Precision::default()
+        )
+    );
+}
+
+#[test]
+fn fees_and_adjustments() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Fee, 0, 2, Some(3)),
+        tx(TransactionType::Adjustment, 0, 3, Some(4)),
+        Transaction {
+            negative: true,
+            ..tx(TransactionType::Adjustment, 0, 4, Some(2))
+        },
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(SignedCents::new(9), client.balance("").available());
+    // neither may drive the available funds negative
+    assert_eq!(
+        Err(TransactionError::InsufficientBalance {
+            available: Precision::default().amount(9),
+            requested: Precision::default().amount(10)
+        }),
+        client.handle_tx(
+            tx(TransactionType::Fee, 0, 5, Some(10)),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::InsufficientBalance {
+            available: Precision::default().amount(9),
+            requested: Precision::default().amount(10)
+        }),
+        client.handle_tx(
             Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 0,
-                tx: 2,
-                amount: Some(Cents::new(1)),
+                negative: true,
+                ..tx(TransactionType::Adjustment, 0, 5, Some(10))
             },
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::TransactionIndisputable(
+            TransactionType::Fee
+        )),
+        client.handle_tx(
+            tx(TransactionType::Dispute, 0, 2, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::TransactionIndisputable(
+            TransactionType::Adjustment
+        )),
+        client.handle_tx(
+            tx(TransactionType::Dispute, 0, 3, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    // still charged on frozen accounts
+    client
+        .handle_tx(
+            tx(TransactionType::Lock, 0, 0, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Fee, 0, 6, Some(1)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
             Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
+                negative: true,
+                ..tx(TransactionType::Adjustment, 0, 7, Some(1))
             },
-        ],
-        Cents::new(68),
-        Cents::new(0),
-        false,
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(SignedCents::new(7), client.balance("").available());
+}
+
+#[test]
+fn reversal() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Deposit, 0, 2, Some(10)),
+        tx(TransactionType::Fee, 0, 3, Some(2)),
+        Transaction {
+            negative: true,
+            ..tx(TransactionType::Adjustment, 0, 4, Some(1))
+        },
+        tx(TransactionType::Withdrawal, 0, 5, Some(4)),
+        tx(TransactionType::Reversal, 0, 3, None),
+        tx(TransactionType::Reversal, 0, 4, None),
+        tx(TransactionType::Reversal, 0, 1, None),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(SignedCents::new(6), client.balance("").available());
+    assert_eq!(DisputeState::Reversed, client.transactions[&1].state());
+    assert_eq!(
+        Err(TransactionError::AlreadyReversed(1)),
+        client.handle_tx(
+            tx(TransactionType::Reversal, 0, 1, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::AlreadyReversed(1)),
+        client.handle_tx(
+            tx(TransactionType::Dispute, 0, 1, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::InsufficientBalance {
+            available: Precision::default().amount(6),
+            requested: Precision::default().amount(10)
+        }),
+        client.handle_tx(
+            tx(TransactionType::Reversal, 0, 2, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    client
+        .handle_tx(
+            tx(TransactionType::Dispute, 0, 5, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::TransactionUnderDispute(5)),
+        client.handle_tx(
+            tx(TransactionType::Reversal, 0, 5, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::TransactionDoesNotExist(9)),
+        client.handle_tx(
+            tx(TransactionType::Reversal, 0, 9, None),
+            &policy,
+            Precision::default()
+        )
+    );
+}
+
+#[test]
+fn negative_balance() {
+    let policy = DefaultPolicy {
+        allow_negative_balance: true,
+        ..DefaultPolicy::default()
+    };
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Withdrawal, 0, 2, Some(8)),
+        tx(TransactionType::Dispute, 0, 1, None),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    // the full amount is held even though most of it was withdrawn
+    assert_eq!(SignedCents::new(-8), client.balance("").available());
+    assert_eq!(Cents::new(10), client.balance("").held());
+    assert_eq!(2, client.balance("").total());
+    // cannot withdraw into debt
+    assert_eq!(
+        Err(TransactionError::InsufficientBalance {
+            available: Precision::default().amount(-8),
+            requested: Precision::default().amount(1)
+        }),
+        client.handle_tx(
+            tx(TransactionType::Withdrawal, 0, 3, Some(1)),
+            &policy,
+            Precision::default()
+        )
+    );
+    client
+        .handle_tx(
+            tx(TransactionType::Chargeback, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(-8, client.balance("").total());
+    // deposits pay off the debt
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 4, Some(5)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(SignedCents::new(-3), client.balance("").available());
+}
+
+#[test]
+fn authorization() {
+    let policy = DefaultPolicy::default();
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Authorize, 0, 2, Some(6)),
+        tx(TransactionType::Capture, 0, 2, Some(2)),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(SignedCents::new(4), client.balance("").available());
+    assert_eq!(Cents::new(4), client.balance("").held());
+    assert_eq!(
+        Err(TransactionError::ExceedsAuthorizedAmount {
+            tx: 2,
+            requested: Precision::default().amount(5),
+            remaining: Precision::default().amount(4)
+        }),
+        client.handle_tx(
+            tx(TransactionType::Capture, 0, 2, Some(5)),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::DuplicateTransaction(2)),
+        client.handle_tx(
+            tx(TransactionType::Deposit, 0, 2, Some(5)),
+            &policy,
+            Precision::default()
+        )
+    );
+    // voiding releases what was not captured
+    client
+        .handle_tx(
+            tx(TransactionType::Void, 0, 2, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(SignedCents::new(8), client.balance("").available());
+    assert_eq!(Cents::ZERO, client.balance("").held());
+    assert_eq!(
+        Err(TransactionError::AuthorizationVoided(2)),
+        client.handle_tx(
+            tx(TransactionType::Capture, 0, 2, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::UnknownAuthorization(1)),
+        client.handle_tx(
+            tx(TransactionType::Capture, 0, 1, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    client
+        .handle_tx(
+            tx(TransactionType::Authorize, 0, 3, Some(8)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Capture, 0, 3, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::AuthorizationCaptured(3)),
+        client.handle_tx(
+            tx(TransactionType::Void, 0, 3, None),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(SignedCents::ZERO, client.balance("").available());
+    assert_eq!(Cents::ZERO, client.balance("").held());
+    assert_eq!(
+        Err(TransactionError::InsufficientBalance {
+            available: Precision::default().amount(0),
+            requested: Precision::default().amount(1)
+        }),
+        client.handle_tx(
+            tx(TransactionType::Authorize, 0, 4, Some(1)),
+            &policy,
+            Precision::default()
+        )
+    );
+}
+
+#[test]
+fn authorization_expiry() {
+    let policy = DefaultPolicy {
+        authorization_expiry: Some(2),
+        ..DefaultPolicy::default()
+    };
+    let mut client = Client::new();
+    for tx in [
+        tx(TransactionType::Deposit, 0, 1, Some(10)),
+        tx(TransactionType::Authorize, 0, 2, Some(6)),
+        tx(TransactionType::Deposit, 0, 3, Some(1)),
+        tx(TransactionType::Capture, 0, 2, Some(1)),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(Cents::new(5), client.balance("").held());
+    // the third transaction after the authorization expires it first
+    assert_eq!(
+        Err(TransactionError::AuthorizationExpired(2)),
+        client.handle_tx(
+            tx(TransactionType::Capture, 0, 2, Some(1)),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(SignedCents::new(10), client.balance("").available());
+    assert_eq!(Cents::ZERO, client.balance("").held());
+}
+
+#[test]
+fn authorization_expiry_rejected() {
+    let policy = DefaultPolicy {
+        authorization_expiry: Some(1),
+        ..DefaultPolicy::default()
+    };
+    let mut client = Client::new();
+    client
+        .handle_tx(
+            tx(TransactionType::Deposit, 0, 1, Some(10)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    client
+        .handle_tx(
+            tx(TransactionType::Authorize, 0, 2, Some(5)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    // rejected transactions do not count towards expiry
+    for _ in 0..2 {
+        assert!(client
+            .handle_tx(
+                tx(TransactionType::Withdrawal, 0, 3, Some(100)),
+                &policy,
+                Precision::default()
+            )
+            .is_err());
+    }
+    assert_eq!(Cents::new(5), client.balance("").held());
+    client
+        .handle_tx(
+            tx(TransactionType::Capture, 0, 2, Some(5)),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(SignedCents::new(5), client.balance("").available());
+    assert_eq!(Cents::ZERO, client.balance("").held());
+}
+
+fn at(tx: Transaction, timestamp: u64) -> Transaction {
+    Transaction {
+        timestamp: Some(timestamp),
+        ..tx
+    }
+}
+
+#[test]
+fn timestamps() {
+    let policy = DefaultPolicy {
+        dispute_window: Some(100),
+        ..DefaultPolicy::default()
+    };
+    let mut client = Client::new();
+    for tx in [
+        at(tx(TransactionType::Deposit, 0, 1, Some(10)), 1000),
+        // transactions without a timestamp are not ordered
+        tx(TransactionType::Deposit, 0, 2, Some(10)),
+        at(tx(TransactionType::Deposit, 0, 3, Some(10)), 1000),
+        at(tx(TransactionType::Dispute, 0, 3, None), 1100),
+    ] {
+        client.handle_tx(tx, &policy, Precision::default()).unwrap();
+    }
+    assert_eq!(
+        Err(TransactionError::TimestampOutOfOrder {
+            timestamp: 1099,
+            last: 1100
+        }),
+        client.handle_tx(
+            at(tx(TransactionType::Deposit, 0, 4, Some(1)), 1099),
+            &policy,
+            Precision::default()
+        )
+    );
+    assert_eq!(
+        Err(TransactionError::DisputeWindowExpired { tx: 1, window: 100 }),
+        client.handle_tx(
+            at(tx(TransactionType::Dispute, 0, 1, None), 1101),
+            &policy,
+            Precision::default()
+        )
     );
+    // disputes without a timestamp cannot be checked against the window
+    client
+        .handle_tx(
+            tx(TransactionType::Dispute, 0, 1, None),
+            &policy,
+            Precision::default(),
+        )
+        .unwrap();
+    assert_eq!(Cents::new(20), client.balance("").held());
 }
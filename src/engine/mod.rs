@@ -1,47 +1,453 @@
 //! The transaction processing engine
 
+#[doc(hidden)]
+pub mod parallel;
 #[cfg(test)]
 mod test;
 
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::client::{Client, ClientId};
+use crate::asset::{AssetConfig, AssetId};
+use crate::cents::{Cents, Precision};
+use crate::client::{AccountStatus, Balance, Client, ClientId};
 use crate::err::TransactionError;
-use crate::transaction::Transaction;
+use crate::output::{AccountsDisplay, OutputFormat};
+use crate::policy::{DefaultPolicy, Policy};
+use crate::transaction::{
+    DisputableTransactionType, DisputeState, Transaction, TransactionId, TransactionType,
+};
 
+/// Order in which accounts are output. Ties are broken by client ID, then asset
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum AccountOrdering {
+    #[default]
+    ClientId,
+    Total,
+    Available,
+}
+
+impl FromStr for AccountOrdering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::ClientId),
+            "total" => Ok(Self::Total),
+            "available" => Ok(Self::Available),
+            _ => Err(format!("Unknown account ordering: {}", s)),
+        }
+    }
+}
+
+/// A client's balance in a single asset, i.e. a row of output
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Account<'a> {
+    pub client: ClientId,
+    pub asset: &'a str,
+    pub balance: Balance,
+    pub status: AccountStatus,
+    /// Number of transactions in the asset currently under dispute
+    pub open_disputes: usize,
+    /// Number of transactions in the asset that were charged back
+    pub charged_back: usize,
+}
+
+impl Account<'_> {
+    /// Whether the account can no longer withdraw, i.e. is locked or closed
+    pub fn locked(&self) -> bool {
+        self.status != AccountStatus::Active
+    }
+}
+
+/// Every client's state, to which transactions are applied in chronological order.
+/// Amounts are `Cents` at the engine's `precision()`
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "PersistedEngine")]
 pub struct Engine {
     clients: HashMap<ClientId, Client>,
+    /// Configured per run rather than persisted
+    #[serde(skip)]
+    assets: AssetConfig,
+    #[serde(skip)]
+    policy: Arc<dyn Policy>,
+    /// The client each transaction ID was first recorded by, across all clients.
+    /// Derived from `clients`, see `index_transactions()`
+    #[serde(skip)]
+    tx_owners: HashMap<TransactionId, ClientId>,
+}
+
+/// The serialized part of an `Engine`, from which the rest is derived
+#[derive(Deserialize)]
+struct PersistedEngine {
+    clients: HashMap<ClientId, Client>,
+}
+
+impl From<PersistedEngine> for Engine {
+    fn from(persisted: PersistedEngine) -> Self {
+        let mut engine = Self {
+            clients: persisted.clients,
+            ..Self::default()
+        };
+        engine.index_transactions();
+        engine
+    }
+}
+
+fn default_policy() -> Arc<dyn Policy> {
+    Arc::new(DefaultPolicy::default())
 }
 
 impl Engine {
-    pub fn new() -> Self {
+    /// An engine without any clients that applies transactions under `policy`
+    pub fn new<P: Policy + 'static>(policy: P) -> Self {
+        Self::with_policy(Arc::new(policy))
+    }
+
+    fn with_policy(policy: Arc<dyn Policy>) -> Self {
         Self {
             clients: HashMap::new(),
+            assets: AssetConfig::default(),
+            policy,
+            tx_owners: HashMap::new(),
         }
     }
 
+    /// Replaces the asset configuration, which amounts are validated against.
+    /// Amounts already applied are not converted to a new precision, so it should only change
+    /// before any transactions are applied
+    pub fn set_assets(&mut self, assets: AssetConfig) {
+        self.assets = assets;
+    }
+
+    /// What a cent of the engine's amounts is, which they are parsed and displayed at
+    pub fn precision(&self) -> Precision {
+        self.assets.precision()
+    }
+
+    /// Replaces the policy, e.g. of an engine restored from a snapshot
+    pub fn set_policy<P: Policy + 'static>(&mut self, policy: P) {
+        self.policy = Arc::new(policy);
+    }
+
+    /// Applies a transaction, or rejects it and leaves every client unchanged
     pub fn handle_tx(&mut self, tx: Transaction) -> Result<(), TransactionError> {
-        let client_id = tx.client;
-        let client = self.clients.entry(client_id).or_insert(Client::new());
-        client.handle_tx(tx)
+        let client = tx.client;
+        self.try_tx(tx)?;
+        if let Some(period) = self.policy.dispute_auto_resolve() {
+            self.auto_resolve(client, period);
+        }
+        Ok(())
+    }
+
+    /// Validates and applies a transaction, without resolving overdue disputes
+    fn try_tx(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+        // parsed rows are already checked, transactions built by library callers are not
+        if tx.negative && tx.transaction_type != TransactionType::Adjustment {
+            return Err(TransactionError::NegativeAmount(tx.transaction_type));
+        }
+        if let Some(amount) = tx.amount {
+            self.assets.validate(self.amount_asset(&tx), amount)?;
+        }
+        self.check_tx_id(&tx)?;
+        let (id, client_id) = (tx.tx, tx.client);
+        let creates_record = tx.transaction_type.creates_record();
+        let result = self.apply_tx(tx);
+        if result.is_ok() && creates_record {
+            self.tx_owners.entry(id).or_insert(client_id);
+        }
+        result
+    }
+
+    /// Asset `tx`'s amount is in. Rows referring to one of the client's earlier transactions
+    /// or authorizations, e.g. partial disputes and captures, are in its asset whatever
+    /// their own asset column says
+    fn amount_asset<'a>(&'a self, tx: &'a Transaction) -> &'a str {
+        let referenced = match tx.transaction_type.references_record() {
+            true => self
+                .clients
+                .get(&tx.client)
+                .and_then(|client| client.record_asset(tx.tx)),
+            false => None,
+        };
+        referenced.unwrap_or(&tx.asset)
+    }
+
+    fn apply_tx(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+        match tx.transaction_type {
+            TransactionType::Transfer => return self.handle_transfer(tx),
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if let Some((source, destination)) = self.transfer_clients(&tx) {
+                    return self.handle_transfer_dispute(tx, source, destination);
+                }
+            }
+            _ => {}
+        }
+        let precision = self.precision();
+        let client = self.clients.entry(tx.client).or_insert(Client::new());
+        client.handle_tx(tx, self.policy.as_ref(), precision)
+    }
+
+    /// Checks `tx`'s ID against the transactions of all other clients: new transactions
+    /// may not reuse another client's ID and rows referring to another client's transaction
+    /// are rejected as such. If the policy makes IDs per client, clients only ever see
+    /// their own transactions, so there is nothing to check
+    fn check_tx_id(&self, tx: &Transaction) -> Result<(), TransactionError> {
+        if self.policy.per_client_tx_ids() {
+            return Ok(());
+        }
+        let owner = match self.tx_owners.get(&tx.tx) {
+            Some(owner) if *owner != tx.client => *owner,
+            _ => return Ok(()),
+        };
+        if tx.transaction_type.creates_record() {
+            return Err(TransactionError::DuplicateTransaction(tx.tx));
+        }
+        let own_record = self
+            .clients
+            .get(&tx.client)
+            .is_some_and(|client| client.has_tx(tx.tx));
+        if tx.transaction_type.references_record() && !own_record {
+            return Err(TransactionError::TransactionBelongsToOtherClient { tx: tx.tx, owner });
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the index of transaction IDs from the clients' records,
+    /// e.g. after deserializing or merging engines
+    pub(crate) fn index_transactions(&mut self) {
+        self.tx_owners.clear();
+        let mut client_ids: Vec<ClientId> = self.clients.keys().copied().collect();
+        client_ids.sort_unstable();
+        for client_id in client_ids {
+            let client = &self.clients[&client_id];
+            for id in client.tx_ids() {
+                let owner = match client.transaction(id).map(|tx| tx.transaction_type) {
+                    Some(DisputableTransactionType::TransferIn { from }) => from,
+                    _ => client_id,
+                };
+                self.tx_owners.entry(id).or_insert(owner);
+            }
+        }
+    }
+
+    /// Resolves the client's disputes that have been open for longer than `period` as of its
+    /// latest timestamp. Each is resolved like a `Resolve` row without an amount, so that
+    /// transfers are resolved on both sides
+    fn auto_resolve(&mut self, client: ClientId, period: u64) {
+        let overdue = match self.clients.get(&client) {
+            Some(client) => match client.last_timestamp() {
+                Some(now) => client.overdue_disputes(now, period),
+                None => return,
+            },
+            None => return,
+        };
+        for id in overdue {
+            let resolve = Transaction {
+                transaction_type: TransactionType::Resolve,
+                client,
+                tx: id,
+                amount: None,
+                asset: AssetId::new(),
+                to: None,
+                negative: false,
+                timestamp: None,
+            };
+            // a resolve that fails leaves the transaction disputed, like a rejected row
+            let _ = self.try_tx(resolve);
+        }
+    }
+
+    /// Moves funds between two clients, either fully or not at all
+    fn handle_transfer(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+        let to = tx.to.ok_or(TransactionError::NoDestination)?;
+        if to == tx.client {
+            return Err(TransactionError::TransferToSelf);
+        }
+        for client in [tx.client, to] {
+            // clients that don't exist yet have nothing to check or expire
+            if let Some(client) = self.clients.get_mut(&client) {
+                client.begin_tx(tx.timestamp, self.policy.as_ref())?;
+            }
+        }
+        // both sides are checked against a new client if they don't exist yet,
+        // so that rejected transfers don't create accounts
+        let new = Client::new();
+        let source = self.clients.get(&tx.client).unwrap_or(&new);
+        let out = source.prepare_transfer_out(&tx, to, self.precision())?;
+        let destination = self.clients.get(&to).unwrap_or(&new);
+        let into = destination.prepare_transfer_in(&tx, self.policy.as_ref())?;
+        self.clients
+            .entry(tx.client)
+            .or_insert(Client::new())
+            .apply_transfer(out);
+        self.clients
+            .entry(to)
+            .or_insert(Client::new())
+            .apply_transfer(into);
+        Ok(())
+    }
+
+    /// Source and destination clients if `tx` refers to a transfer, from either side
+    fn transfer_clients(&self, tx: &Transaction) -> Option<(ClientId, ClientId)> {
+        let disputed = self.clients.get(&tx.client)?.transaction(tx.tx)?;
+        match disputed.transaction_type {
+            DisputableTransactionType::TransferIn { from } => Some((from, tx.client)),
+            DisputableTransactionType::TransferOut { to } => Some((tx.client, to)),
+            _ => None,
+        }
+    }
+
+    /// A transfer is disputed as a unit: the destination's side is disputed like a deposit,
+    /// and the source's side follows it. Chargebacks return the funds to the source
+    fn handle_transfer_dispute(
+        &mut self,
+        tx: Transaction,
+        source: ClientId,
+        destination: ClientId,
+    ) -> Result<(), TransactionError> {
+        let id = tx.tx;
+        // unwrap safety: both sides of a transfer are recorded when it is applied
+        let transfer_in = self.clients[&destination].transaction(id).unwrap();
+        let asset = transfer_in.asset.clone();
+        let charged_back = transfer_in.charged_back;
+        if tx.transaction_type == TransactionType::Chargeback {
+            let amount = tx.amount.unwrap_or(transfer_in.held).min(transfer_in.held);
+            self.clients[&source].check_credit(&asset, amount)?;
+        }
+        let (policy, precision) = (self.policy.as_ref(), self.assets.precision());
+        let dest = self.clients.get_mut(&destination).unwrap();
+        dest.handle_tx(
+            Transaction {
+                client: destination,
+                ..tx
+            },
+            policy,
+            precision,
+        )?;
+        let transfer_in = dest.transaction(id).unwrap();
+        let (state, disputed_at) = (transfer_in.state(), transfer_in.disputed_at);
+        // unwrap safety: charged back funds only ever grow
+        let returned = (transfer_in.charged_back - charged_back).unwrap();
+        let source = self.clients.get_mut(&source).unwrap();
+        source.sync_transfer_out(id, state, disputed_at);
+        if returned != Cents::ZERO {
+            // unwrap safety: checked above
+            source.credit(&asset, returned).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Splits the engine into `n` engines with disjoint sets of clients,
+    /// each client going to the engine at index `shard_of(client_id, n)`
+    pub(crate) fn into_shards(
+        self,
+        n: usize,
+        shard_of: fn(ClientId, usize) -> usize,
+    ) -> Vec<Engine> {
+        let mut shards: Vec<Engine> = (0..n)
+            .map(|_| {
+                let mut shard = Engine::with_policy(self.policy.clone());
+                shard.set_assets(self.assets.clone());
+                shard
+            })
+            .collect();
+        for (client_id, client) in self.clients {
+            shards[shard_of(client_id, n)]
+                .clients
+                .insert(client_id, client);
+        }
+        shards
+    }
+
+    /// Combines engines with disjoint sets of clients into one,
+    /// keeping the asset configuration and policy of the first
+    pub(crate) fn merge(engines: Vec<Engine>) -> Self {
+        let mut engines = engines.into_iter();
+        let mut merged = engines.next().unwrap_or_default();
+        for engine in engines {
+            merged.clients.extend(engine.clients);
+        }
+        merged.index_transactions();
+        merged
+    }
+
+    /// Moves client `id` out of the engine, e.g. into the shard of a client it transacts with
+    pub(crate) fn remove_client(&mut self, id: ClientId) -> Option<Client> {
+        self.clients.remove(&id)
+    }
+
+    pub(crate) fn insert_client(&mut self, id: ClientId, client: Client) {
+        self.clients.insert(id, client);
+    }
+
+    /// A read-only view of a client, if it ever transacted
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    /// Every client's balance in every asset, in the given order
+    pub fn accounts(&self, ordering: AccountOrdering) -> Vec<Account<'_>> {
+        let mut accounts: Vec<Account> = self
+            .clients
+            .iter()
+            .flat_map(|(id, client)| {
+                client
+                    .balances()
+                    .into_iter()
+                    .map(move |(asset, balance)| Account {
+                        client: *id,
+                        asset,
+                        balance,
+                        status: client.status(),
+                        open_disputes: client.count_in_state(asset, DisputeState::Disputed),
+                        charged_back: client.count_in_state(asset, DisputeState::ChargedBack),
+                    })
+            })
+            .collect();
+        match ordering {
+            AccountOrdering::ClientId => accounts.sort_by_key(|a| (a.client, a.asset)),
+            AccountOrdering::Total => {
+                accounts.sort_by_key(|a| (a.balance.total(), a.client, a.asset))
+            }
+            AccountOrdering::Available => {
+                accounts.sort_by_key(|a| (a.balance.available(), a.client, a.asset))
+            }
+        }
+        accounts
+    }
+
+    /// Displays all accounts in the given order and format.
+    /// `Engine`'s `Display` impl outputs csv with accounts ordered by client ID
+    pub fn display(&self, ordering: AccountOrdering, format: OutputFormat) -> AccountsDisplay<'_> {
+        AccountsDisplay::new(self, ordering, format)
+    }
+
+    pub(crate) fn assets(&self) -> &AssetConfig {
+        &self.assets
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::with_policy(default_policy())
+    }
+}
+
+/// Engines are equal if they hold the same state. Policies are behaviour and are not compared
+impl PartialEq for Engine {
+    fn eq(&self, other: &Self) -> bool {
+        self.clients == other.clients && self.assets == other.assets
     }
 }
 
 impl fmt::Display for Engine {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "client,available,held,total,locked")?;
-        for (client_id, client) in self.clients.iter() {
-            write!(
-                f,
-                "\n{},{},{},{},{}",
-                client_id,
-                client.available(),
-                client.held(),
-                client.total(),
-                client.frozen()
-            )?;
-        }
-        Ok(())
+        self.display(AccountOrdering::default(), OutputFormat::Csv)
+            .fmt(f)
     }
 }
@@ -0,0 +1,285 @@
+//! An engine that processes transactions across multiple threads.
+//!
+//! Clients are independent of each other, so transactions are partitioned by client ID
+//! across worker threads, each owning an `Engine` with a disjoint set of clients.
+//! Each client's transactions are always sent to the same worker in input order,
+//! so per-client ordering is preserved. Transaction IDs must be per client, as a worker
+//! cannot tell whether an ID is in use by a client on another worker.
+//!
+//! Transfers are the exception, as they involve two clients that may be on different workers.
+//! Transfers and the rows that may dispute, resolve or charge them back are applied once the
+//! workers of every client involved are idle, with those clients moved into a single engine
+//! for the time being, so the results are the same as on a single thread.
+
+#[cfg(test)]
+mod test;
+
+use std::collections::{BTreeSet, HashMap};
+use std::mem;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::client::ClientId;
+use crate::report::Rejected;
+use crate::transaction::{DisputableTransactionType, Transaction, TransactionType};
+
+use super::Engine;
+
+/// Number of transactions sent to a worker at once
+const BATCH_SIZE: usize = 1024;
+/// Number of batches that can be queued up for a worker before dispatching blocks
+const QUEUED_BATCHES: usize = 16;
+
+type Batch = Vec<(u64, Transaction)>;
+
+enum Message {
+    Batch(Batch),
+    /// Acknowledged once every batch sent before it has been processed
+    Barrier(SyncSender<()>),
+}
+
+/// A worker's engine, along with the outcomes of the transactions applied to it
+struct Shard {
+    engine: Engine,
+    applied: u64,
+    rejected: Vec<Rejected>,
+}
+
+impl Shard {
+    fn handle_tx(&mut self, line: u64, tx: Transaction) {
+        let summary_tx = tx.clone();
+        match self.engine.handle_tx(tx) {
+            Ok(()) => self.applied += 1,
+            Err(err) => self.rejected.push(Rejected {
+                line,
+                tx: summary_tx,
+                err,
+            }),
+        }
+    }
+}
+
+/// The final state after all transactions have been processed
+pub struct ParallelResult {
+    pub engine: Engine,
+    pub applied: u64,
+    /// In order of input line number
+    pub rejected: Vec<Rejected>,
+}
+
+pub struct ParallelEngine {
+    senders: Vec<SyncSender<Message>>,
+    workers: Vec<JoinHandle<()>>,
+    shards: Vec<Arc<Mutex<Shard>>>,
+    batches: Vec<Batch>,
+    /// The clients on other shards that each client has transferred funds to or from
+    counterparties: HashMap<ClientId, BTreeSet<ClientId>>,
+    /// Whether any row may resolve the client's disputes, including those of transfers
+    auto_resolve: bool,
+}
+
+impl ParallelEngine {
+    /// Continues processing from the state in `engine` using `threads` worker threads.
+    /// The engine's policy must make transaction IDs per client
+    pub fn new(engine: Engine, threads: usize) -> Self {
+        assert!(threads > 0, "ParallelEngine requires at least 1 thread");
+        assert!(
+            engine.policy.per_client_tx_ids(),
+            "ParallelEngine requires per-client transaction IDs"
+        );
+        let auto_resolve = engine.policy.dispute_auto_resolve().is_some();
+        let transfers = transfer_clients(&engine);
+        let mut senders = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+        let mut shards = Vec::with_capacity(threads);
+        for engine in engine.into_shards(threads, shard_of) {
+            let (sender, receiver) = mpsc::sync_channel::<Message>(QUEUED_BATCHES);
+            let shard = Arc::new(Mutex::new(Shard {
+                engine,
+                applied: 0,
+                rejected: Vec::new(),
+            }));
+            senders.push(sender);
+            shards.push(shard.clone());
+            workers.push(thread::spawn(move || {
+                for message in receiver {
+                    match message {
+                        Message::Batch(batch) => {
+                            // unwrap safety: panics if the dispatching thread panicked
+                            let mut shard = shard.lock().unwrap();
+                            for (line, tx) in batch {
+                                shard.handle_tx(line, tx);
+                            }
+                        }
+                        Message::Barrier(ack) => {
+                            // the dispatching thread waits for the ack, unless it panicked
+                            let _ = ack.send(());
+                        }
+                    }
+                }
+            }));
+        }
+        let mut engine = Self {
+            senders,
+            workers,
+            shards,
+            batches: (0..threads)
+                .map(|_| Vec::with_capacity(BATCH_SIZE))
+                .collect(),
+            counterparties: HashMap::new(),
+            auto_resolve,
+        };
+        for (client, other) in transfers {
+            engine.add_counterparties(client, other);
+        }
+        engine
+    }
+
+    /// Queues a transaction to be processed by the worker responsible for its client,
+    /// or applies it right away if it involves clients of other workers
+    pub fn handle_tx(&mut self, line: u64, tx: Transaction) {
+        let others = self.other_clients(&tx);
+        if !others.is_empty() {
+            return self.handle_across(line, tx, others);
+        }
+        let shard = shard_of(tx.client, self.shards.len());
+        self.batches[shard].push((line, tx));
+        if self.batches[shard].len() >= BATCH_SIZE {
+            self.send(shard);
+        }
+    }
+
+    /// Waits for all queued transactions to be processed and merges the workers' results
+    pub fn finish(mut self) -> ParallelResult {
+        for shard in 0..self.senders.len() {
+            self.send(shard);
+        }
+        // hang up so that workers exit once they're done
+        self.senders.clear();
+        for worker in self.workers {
+            // unwrap safety: panics if a worker panicked
+            worker.join().unwrap();
+        }
+        let mut engines = Vec::with_capacity(self.shards.len());
+        let mut applied = 0;
+        let mut rejected = Vec::new();
+        for shard in self.shards {
+            // unwrap safety: the workers holding the other references have exited
+            let shard = Arc::into_inner(shard).unwrap().into_inner().unwrap();
+            engines.push(shard.engine);
+            applied += shard.applied;
+            rejected.extend(shard.rejected);
+        }
+        rejected.sort_by_key(|rejected| rejected.line);
+        ParallelResult {
+            engine: Engine::merge(engines),
+            applied,
+            rejected,
+        }
+    }
+
+    /// Clients on other shards that `tx` may involve: the destination of a transfer, and
+    /// the other sides of the client's transfers for rows that may dispute, resolve or
+    /// charge them back
+    fn other_clients(&mut self, tx: &Transaction) -> BTreeSet<ClientId> {
+        if let (TransactionType::Transfer, Some(to)) = (tx.transaction_type, tx.to) {
+            self.add_counterparties(tx.client, to);
+        }
+        let settles = matches!(
+            tx.transaction_type,
+            TransactionType::Transfer
+                | TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+        );
+        match self.counterparties.get(&tx.client) {
+            Some(others) if settles || self.auto_resolve => others.clone(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    /// Records that `client` and `other` transferred funds, if they are on different shards.
+    /// Rejected transfers are recorded as well, which is harmless
+    fn add_counterparties(&mut self, client: ClientId, other: ClientId) {
+        let shards = self.shards.len();
+        if shard_of(client, shards) == shard_of(other, shards) {
+            return;
+        }
+        self.counterparties.entry(client).or_default().insert(other);
+        self.counterparties.entry(other).or_default().insert(client);
+    }
+
+    /// Applies a transaction involving clients of other shards once the workers of all
+    /// of them are idle, with the other clients moved into the shard of the transaction's
+    /// client until it has been applied
+    fn handle_across(&mut self, line: u64, tx: Transaction, others: BTreeSet<ClientId>) {
+        let n = self.shards.len();
+        let home = shard_of(tx.client, n);
+        let mut involved: Vec<usize> = others.iter().map(|other| shard_of(*other, n)).collect();
+        involved.push(home);
+        involved.sort_unstable();
+        involved.dedup();
+        self.drain(&involved);
+        // unwrap safety: panics if a worker panicked
+        let mut shard = self.shards[home].lock().unwrap();
+        for &other in others.iter() {
+            let mut other_shard = self.shards[shard_of(other, n)].lock().unwrap();
+            if let Some(client) = other_shard.engine.remove_client(other) {
+                shard.engine.insert_client(other, client);
+            }
+        }
+        shard.handle_tx(line, tx);
+        for &other in others.iter() {
+            if let Some(client) = shard.engine.remove_client(other) {
+                let mut other_shard = self.shards[shard_of(other, n)].lock().unwrap();
+                other_shard.engine.insert_client(other, client);
+            }
+        }
+    }
+
+    /// Waits for the workers of `shards` to process every transaction queued for them
+    fn drain(&mut self, shards: &[usize]) {
+        let (ack, acks) = mpsc::sync_channel(shards.len());
+        for &shard in shards {
+            self.send(shard);
+            // unwrap safety: panics if the worker panicked
+            self.senders[shard]
+                .send(Message::Barrier(ack.clone()))
+                .unwrap();
+        }
+        for _ in shards {
+            acks.recv().unwrap();
+        }
+    }
+
+    fn send(&mut self, shard: usize) {
+        if self.batches[shard].is_empty() {
+            return;
+        }
+        let batch = mem::replace(&mut self.batches[shard], Vec::with_capacity(BATCH_SIZE));
+        // unwrap safety: panics if the worker panicked
+        self.senders[shard].send(Message::Batch(batch)).unwrap();
+    }
+}
+
+/// Pairs of clients with a transfer between them in `engine`
+fn transfer_clients(engine: &Engine) -> Vec<(ClientId, ClientId)> {
+    let mut pairs = Vec::new();
+    for (&client_id, client) in engine.clients.iter() {
+        for id in client.tx_ids() {
+            match client.transaction(id).map(|tx| tx.transaction_type) {
+                Some(DisputableTransactionType::TransferIn { from: other })
+                | Some(DisputableTransactionType::TransferOut { to: other }) => {
+                    pairs.push((client_id, other))
+                }
+                _ => (),
+            }
+        }
+    }
+    pairs
+}
+
+fn shard_of(client: ClientId, shards: usize) -> usize {
+    client as usize % shards
+}
@@ -0,0 +1,163 @@
+use crate::cents::{Cents, Precision};
+use crate::err::TransactionError;
+use crate::policy::DefaultPolicy;
+use crate::transaction::{tx, Transaction, TransactionType};
+
+use super::*;
+
+/// An engine with per-client transaction IDs, as sharding requires
+fn engine() -> Engine {
+    Engine::new(DefaultPolicy {
+        per_client_tx_ids: true,
+        ..DefaultPolicy::default()
+    })
+}
+
+/// Deposits, withdrawals and disputes across many clients, some of which are rejected
+fn workload() -> Vec<Transaction> {
+    let mut txs = Vec::new();
+    for i in 0..10_000_u32 {
+        let client = (i % 97) as u16;
+        let tx_type = match i % 7 {
+            0..=2 => TransactionType::Deposit,
+            3..=4 => TransactionType::Withdrawal,
+            5 => TransactionType::Dispute,
+            _ => TransactionType::Resolve,
+        };
+        let (tx_id, amount) = match tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => (i, Some(u64::from(i % 13))),
+            _ => (i.saturating_sub(97 * (i % 3)), None),
+        };
+        txs.push(tx(tx_type, client, tx_id, amount));
+    }
+    txs
+}
+
+/// Deposits and transfers between a few clients, with disputes of the transfers from either
+/// side that are resolved, charged back or left open until they are resolved automatically
+fn transfer_workload() -> Vec<Transaction> {
+    let mut txs = Vec::new();
+    for i in 0..5_000_u32 {
+        let client = |i: u32| (i % 13) as u16;
+        let destination = |i: u32| (i * 7 % 13) as u16;
+        let tx = match i % 6 {
+            0 | 1 => tx(
+                TransactionType::Deposit,
+                client(i),
+                i,
+                Some(u64::from(i % 17 + 1)),
+            ),
+            2 => Transaction {
+                to: Some(destination(i)),
+                ..tx(
+                    TransactionType::Transfer,
+                    client(i),
+                    i,
+                    Some(u64::from(i % 5 + 1)),
+                )
+            },
+            3 => tx(TransactionType::Dispute, destination(i - 1), i - 1, None),
+            4 if i % 12 == 4 => tx(TransactionType::Chargeback, client(i - 2), i - 2, None),
+            4 => tx(TransactionType::Resolve, client(i - 2), i - 2, None),
+            _ => tx(TransactionType::Dispute, client(i - 3), i - 3, None),
+        };
+        txs.push(Transaction {
+            timestamp: Some(i.into()),
+            ..tx
+        });
+    }
+    txs
+}
+
+/// Checks that processing `txs` on any number of threads has the same outcome as
+/// processing them on a single engine, with `before` applied prior to sharding
+fn assert_same_as_sequential(engine: fn() -> Engine, before: &[Transaction], txs: &[Transaction]) {
+    let initial = || {
+        let mut engine = engine();
+        for tx in before {
+            let _ = engine.handle_tx(tx.clone());
+        }
+        engine
+    };
+    let mut sequential = initial();
+    let mut expected_rejected = Vec::new();
+    for (line, tx) in txs.iter().cloned().enumerate() {
+        let summary_tx = tx.clone();
+        if let Err(err) = sequential.handle_tx(tx) {
+            expected_rejected.push(Rejected {
+                line: line as u64,
+                tx: summary_tx,
+                err,
+            });
+        }
+    }
+
+    for threads in 1..=4 {
+        let mut parallel = ParallelEngine::new(initial(), threads);
+        for (line, tx) in txs.iter().cloned().enumerate() {
+            parallel.handle_tx(line as u64, tx);
+        }
+        let result = parallel.finish();
+        assert_eq!(sequential, result.engine);
+        assert_eq!(expected_rejected, result.rejected);
+        assert_eq!((txs.len() - expected_rejected.len()) as u64, result.applied);
+    }
+}
+
+#[test]
+fn same_as_sequential() {
+    assert_same_as_sequential(engine, &[], &workload());
+}
+
+#[test]
+fn transfers_same_as_sequential() {
+    let engine = || {
+        Engine::new(DefaultPolicy {
+            per_client_tx_ids: true,
+            dispute_auto_resolve: Some(30),
+            ..DefaultPolicy::default()
+        })
+    };
+    let txs = transfer_workload();
+    assert_same_as_sequential(engine, &[], &txs);
+    // transfers applied before sharding are disputed across shards as well
+    let (before, after) = txs.split_at(txs.len() / 2);
+    assert_same_as_sequential(engine, before, after);
+}
+
+#[test]
+fn continues_from_engine() {
+    let mut engine = engine();
+    for client in 0..10 {
+        engine
+            .handle_tx(tx(TransactionType::Deposit, client, client.into(), Some(5)))
+            .unwrap();
+    }
+    let mut parallel = ParallelEngine::new(engine, 3);
+    for client in 0..10 {
+        parallel.handle_tx(0, tx(TransactionType::Dispute, client, client.into(), None));
+    }
+    parallel.handle_tx(1, tx(TransactionType::Withdrawal, 4, 100, Some(1)));
+    let result = parallel.finish();
+    assert_eq!(10, result.applied);
+    assert_eq!(
+        vec![Rejected {
+            line: 1,
+            tx: tx(TransactionType::Withdrawal, 4, 100, Some(1)),
+            err: TransactionError::InsufficientBalance {
+                available: Precision::default().amount(0),
+                requested: Precision::default().amount(1),
+            },
+        }],
+        result.rejected
+    );
+    for account in result.engine.accounts(Default::default()) {
+        assert_eq!(Cents::new(5), account.balance.held());
+    }
+}
+
+#[test]
+#[should_panic(expected = "ParallelEngine requires per-client transaction IDs")]
+fn global_tx_ids_unsupported() {
+    ParallelEngine::new(Engine::default(), 2);
+}
@@ -1,7 +1,12 @@
-use crate::cents::Cents;
-use crate::transaction::{Transaction, TransactionType};
+use crate::asset::AssetConfig;
+use crate::cents::{Cents, Precision, SignedCents};
+use crate::err::TransactionError;
+use crate::policy::DefaultPolicy;
+use crate::transaction::{
+    tx, DisputableTransactionType, DisputeState, Transaction, TransactionType,
+};
 
-use super::Engine;
+use super::{AccountOrdering, Engine};
 
 fn run_transactions(engine: &mut Engine, txs: Vec<Transaction>, expected_clients: &str) {
     for tx in txs {
@@ -23,73 +28,623 @@ fn run_transactions(engine: &mut Engine, txs: Vec<Transaction>, expected_clients
 
 #[test]
 fn basic() {
-    let mut engine = Engine::new();
+    let mut engine = Engine::default();
     run_transactions(
         &mut engine,
-        vec![Transaction {
-            transaction_type: TransactionType::Deposit,
-            client: 0,
-            tx: 0,
-            amount: Some(Cents::new(1)),
-        }],
-        "0,0.0001,0.0000,0.0001,false",
+        vec![tx(TransactionType::Deposit, 0, 0, Some(1))],
+        "0,0.0001,0.0000,0.0001,false,active",
     );
 }
 
 #[test]
 fn locked() {
-    let mut engine = Engine::new();
+    let mut engine = Engine::default();
     run_transactions(
         &mut engine,
         vec![
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 0,
-                tx: 1,
-                amount: Some(Cents::new(126929)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Chargeback,
-                client: 0,
-                tx: 1,
-                amount: None,
-            },
+            tx(TransactionType::Deposit, 0, 1, Some(126929)),
+            tx(TransactionType::Dispute, 0, 1, None),
+            tx(TransactionType::Chargeback, 0, 1, None),
         ],
-        "0,0.0000,0.0000,0.0000,true",
+        "0,0.0000,0.0000,0.0000,true,locked",
     );
 }
 
 #[test]
 fn dispute() {
-    let mut engine = Engine::new();
+    let mut engine = Engine::default();
     run_transactions(
         &mut engine,
         vec![
+            tx(TransactionType::Deposit, 2, 1, Some(100)),
+            tx(TransactionType::Withdrawal, 2, 2, Some(99)),
+            tx(TransactionType::Dispute, 2, 2, None),
+        ],
+        "2,0.0001,0.0099,0.0100,false,active",
+    );
+}
+
+fn check_ordering(engine: &Engine, ordering: AccountOrdering, expected_ids: Vec<u16>) {
+    let ids: Vec<u16> = engine
+        .accounts(ordering)
+        .into_iter()
+        .map(|account| account.client)
+        .collect();
+    assert_eq!(expected_ids, ids);
+}
+
+#[test]
+fn ordering() {
+    let mut engine = Engine::default();
+    for tx in [
+        tx(TransactionType::Deposit, 3, 1, Some(5)),
+        tx(TransactionType::Deposit, 1, 2, Some(20)),
+        tx(TransactionType::Deposit, 2, 3, Some(5)),
+        tx(TransactionType::Deposit, 4, 4, Some(10)),
+        tx(TransactionType::Dispute, 4, 4, None),
+    ] {
+        engine.handle_tx(tx).unwrap();
+    }
+    check_ordering(&engine, AccountOrdering::ClientId, vec![1, 2, 3, 4]);
+    check_ordering(&engine, AccountOrdering::Total, vec![2, 3, 4, 1]);
+    check_ordering(&engine, AccountOrdering::Available, vec![4, 2, 3, 1]);
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0020,0.0000,0.0020,false,active\n\
+         2,0.0005,0.0000,0.0005,false,active\n\
+         3,0.0005,0.0000,0.0005,false,active\n\
+         4,0.0000,0.0010,0.0010,false,active",
+        format!("{}", engine)
+    );
+}
+
+#[test]
+fn transfer_between_clients() {
+    let mut engine = Engine::default();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    engine
+        .handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 2, Some(4))
+        })
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::InsufficientBalance {
+            available: Precision::default().amount(6),
+            requested: Precision::default().amount(7)
+        }),
+        engine.handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 3, Some(7))
+        })
+    );
+    assert_eq!(
+        Err(TransactionError::TransferToSelf),
+        engine.handle_tx(Transaction {
+            to: Some(1),
+            ..tx(TransactionType::Transfer, 1, 4, Some(1))
+        })
+    );
+    assert_eq!(
+        Err(TransactionError::NoDestination),
+        engine.handle_tx(Transaction {
+            to: None,
+            ..Transaction {
+                to: Some(2),
+                ..tx(TransactionType::Transfer, 1, 5, Some(1))
+            }
+        })
+    );
+    // rejected atomically: the destination already has a transaction 6,
+    // so the source is not debited either
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 3, 6, Some(1)))
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::DuplicateTransaction(6)),
+        engine.handle_tx(Transaction {
+            to: Some(3),
+            ..tx(TransactionType::Transfer, 1, 6, Some(1))
+        })
+    );
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0006,0.0000,0.0006,false,active\n\
+         2,0.0004,0.0000,0.0004,false,active\n\
+         3,0.0001,0.0000,0.0001,false,active",
+        engine.to_string()
+    );
+    // both clients record the transfer
+    assert_eq!(
+        DisputableTransactionType::TransferOut { to: 2 },
+        engine.clients[&1].transaction(2).unwrap().transaction_type
+    );
+    assert_eq!(
+        DisputableTransactionType::TransferIn { from: 1 },
+        engine.clients[&2].transaction(2).unwrap().transaction_type
+    );
+}
+
+#[test]
+fn rejected_transfer_creates_no_accounts() {
+    let mut engine = Engine::default();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    assert!(engine
+        .handle_tx(Transaction {
+            to: Some(9),
+            ..tx(TransactionType::Transfer, 1, 2, Some(100))
+        })
+        .is_err());
+    // neither side exists
+    assert!(engine
+        .handle_tx(Transaction {
+            to: Some(6),
+            ..tx(TransactionType::Transfer, 5, 3, Some(1))
+        })
+        .is_err());
+    assert_eq!(vec![1], engine.clients.keys().copied().collect::<Vec<_>>());
+}
+
+#[test]
+fn transfer_authorization_expiry() {
+    let mut engine = Engine::new(DefaultPolicy {
+        authorization_expiry: Some(1),
+        ..DefaultPolicy::default()
+    });
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    engine
+        .handle_tx(tx(TransactionType::Authorize, 1, 2, Some(5)))
+        .unwrap();
+    // a transfer counts as a transaction of the source, which expires the authorization
+    engine
+        .handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 3, Some(1))
+        })
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::AuthorizationExpired(2)),
+        engine.handle_tx(tx(TransactionType::Capture, 1, 2, None))
+    );
+    assert_eq!(
+        SignedCents::new(9),
+        engine.clients[&1].balance("").available()
+    );
+    assert_eq!(Cents::ZERO, engine.clients[&1].balance("").held());
+}
+
+#[test]
+fn negative_amount() {
+    let mut engine = Engine::default();
+    assert_eq!(
+        Err(TransactionError::NegativeAmount(TransactionType::Deposit)),
+        engine.handle_tx(Transaction {
+            negative: true,
+            ..tx(TransactionType::Deposit, 1, 1, Some(10))
+        })
+    );
+    assert!(engine.client(1).is_none());
+    engine
+        .handle_tx(Transaction {
+            negative: true,
+            ..tx(TransactionType::Adjustment, 1, 2, Some(0))
+        })
+        .unwrap();
+}
+
+#[test]
+fn transfer_from_frozen() {
+    let mut engine = Engine::default();
+    for tx in [
+        tx(TransactionType::Deposit, 1, 1, Some(10)),
+        tx(TransactionType::Deposit, 1, 2, Some(10)),
+        tx(TransactionType::Dispute, 1, 1, None),
+        tx(TransactionType::Chargeback, 1, 1, None),
+    ] {
+        engine.handle_tx(tx).unwrap();
+    }
+    assert_eq!(
+        Err(TransactionError::AccountFrozen),
+        engine.handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 3, Some(1))
+        })
+    );
+    // frozen accounts may still receive funds, like deposits
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 2, 4, Some(5)))
+        .unwrap();
+    engine
+        .handle_tx(Transaction {
+            to: Some(1),
+            ..tx(TransactionType::Transfer, 2, 5, Some(5))
+        })
+        .unwrap();
+    assert_eq!(
+        SignedCents::new(15),
+        engine.clients[&1].balance("").available()
+    );
+}
+
+#[test]
+fn transfer_dispute() {
+    let mut engine = Engine::default();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    engine
+        .handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 2, Some(4))
+        })
+        .unwrap();
+    // disputed by the source, the funds are held at the destination
+    engine
+        .handle_tx(tx(TransactionType::Dispute, 1, 2, None))
+        .unwrap();
+    assert_eq!(Cents::new(4), engine.clients[&2].balance("").held());
+    assert_eq!(
+        DisputeState::Disputed,
+        engine.clients[&1].transaction(2).unwrap().state()
+    );
+    // resolved from the destination's side
+    engine
+        .handle_tx(tx(TransactionType::Resolve, 2, 2, None))
+        .unwrap();
+    assert_eq!(
+        SignedCents::new(4),
+        engine.clients[&2].balance("").available()
+    );
+    assert_eq!(
+        DisputeState::Resolved,
+        engine.clients[&1].transaction(2).unwrap().state()
+    );
+    engine
+        .handle_tx(tx(TransactionType::Dispute, 1, 2, None))
+        .unwrap();
+    engine
+        .handle_tx(tx(TransactionType::Chargeback, 1, 2, None))
+        .unwrap();
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0010,0.0000,0.0010,false,active\n\
+         2,0.0000,0.0000,0.0000,true,locked",
+        engine.to_string()
+    );
+    assert_eq!(
+        DisputeState::ChargedBack,
+        engine.clients[&1].transaction(2).unwrap().state()
+    );
+}
+
+#[test]
+fn transfer_partial_chargeback() {
+    let mut engine = Engine::default();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    engine
+        .handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 2, Some(6))
+        })
+        .unwrap();
+    for tx in [
+        tx(TransactionType::Dispute, 1, 2, Some(4)),
+        tx(TransactionType::Chargeback, 1, 2, Some(3)),
+    ] {
+        engine.handle_tx(tx).unwrap();
+    }
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0007,0.0000,0.0007,false,active\n\
+         2,0.0002,0.0001,0.0003,true,locked",
+        engine.to_string()
+    );
+}
+
+#[test]
+fn auto_resolve() {
+    let mut engine = Engine::new(DefaultPolicy {
+        dispute_auto_resolve: Some(60),
+        ..DefaultPolicy::default()
+    });
+    let at = |tx: Transaction, timestamp: u64| Transaction {
+        timestamp: Some(timestamp),
+        ..tx
+    };
+    for tx in [
+        at(tx(TransactionType::Deposit, 1, 1, Some(10)), 0),
+        at(tx(TransactionType::Deposit, 1, 2, Some(10)), 0),
+        at(
             Transaction {
-                transaction_type: TransactionType::Deposit,
-                client: 2,
-                tx: 1,
-                amount: Some(Cents::new(100)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Withdrawal,
-                client: 2,
-                tx: 2,
-                amount: Some(Cents::new(99)),
-            },
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client: 2,
-                tx: 2,
-                amount: None,
+                to: Some(2),
+                ..tx(TransactionType::Transfer, 1, 3, Some(5))
             },
-        ],
-        "2,0.0001,0.0099,0.0100,false",
+            0,
+        ),
+        at(tx(TransactionType::Dispute, 1, 1, None), 10),
+        at(tx(TransactionType::Dispute, 1, 3, None), 30),
+        at(tx(TransactionType::Deposit, 1, 4, Some(1)), 71),
+    ] {
+        engine.handle_tx(tx).unwrap();
+    }
+    // the deposit's dispute was open for 60s at most, so it is resolved,
+    // and the transfer's dispute is still open
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0016,0.0000,0.0016,false,active\n\
+         2,0.0000,0.0005,0.0005,false,active",
+        engine.to_string()
+    );
+    // resolved on both sides once the source transacts again
+    engine
+        .handle_tx(at(tx(TransactionType::Deposit, 1, 5, Some(1)), 91))
+        .unwrap();
+    assert_eq!(
+        SignedCents::new(5),
+        engine.clients[&2].balance("").available()
+    );
+    assert_eq!(
+        DisputeState::Resolved,
+        engine.clients[&1].transaction(3).unwrap().state()
+    );
+    assert_eq!(
+        DisputeState::Resolved,
+        engine.clients[&2].transaction(3).unwrap().state()
+    );
+}
+
+#[test]
+fn auto_resolve_rejected() {
+    let mut engine = Engine::new(DefaultPolicy {
+        dispute_auto_resolve: Some(60),
+        ..DefaultPolicy::default()
+    });
+    let at = |tx: Transaction, timestamp: u64| Transaction {
+        timestamp: Some(timestamp),
+        ..tx
+    };
+    engine
+        .handle_tx(at(tx(TransactionType::Deposit, 1, 1, Some(10)), 0))
+        .unwrap();
+    engine
+        .handle_tx(at(tx(TransactionType::Dispute, 1, 1, None), 10))
+        .unwrap();
+    // a rejected row leaves the engine unchanged, so it does not resolve the dispute
+    assert!(matches!(
+        engine.handle_tx(at(tx(TransactionType::Withdrawal, 1, 2, Some(100)), 1000)),
+        Err(TransactionError::InsufficientBalance { .. })
+    ));
+    assert_eq!(
+        DisputeState::Disputed,
+        engine.clients[&1].transaction(1).unwrap().state()
+    );
+    // nor does it advance the client's clock, which later rows are checked at
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 3, Some(1)))
+        .unwrap();
+    assert_eq!(
+        DisputeState::Disputed,
+        engine.clients[&1].transaction(1).unwrap().state()
+    );
+    engine
+        .handle_tx(at(tx(TransactionType::Deposit, 1, 4, Some(1)), 80))
+        .unwrap();
+    assert_eq!(
+        DisputeState::Resolved,
+        engine.clients[&1].transaction(1).unwrap().state()
+    );
+}
+
+#[test]
+fn global_tx_ids() {
+    let mut engine = Engine::default();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::DuplicateTransaction(1)),
+        engine.handle_tx(tx(TransactionType::Deposit, 2, 1, Some(10)))
+    );
+    assert_eq!(
+        Err(TransactionError::TransactionBelongsToOtherClient { tx: 1, owner: 1 }),
+        engine.handle_tx(tx(TransactionType::Dispute, 2, 1, None))
+    );
+    // both sides of a transfer may refer to it
+    engine
+        .handle_tx(Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 2, Some(4))
+        })
+        .unwrap();
+    engine
+        .handle_tx(tx(TransactionType::Dispute, 2, 2, None))
+        .unwrap();
+    engine
+        .handle_tx(tx(TransactionType::Resolve, 1, 2, None))
+        .unwrap();
+    // a rejected transaction does not claim its ID
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 3, 3, Some(u64::MAX)))
+        .unwrap();
+    assert_eq!(
+        Err(TransactionError::Overflow),
+        engine.handle_tx(tx(TransactionType::Deposit, 3, 4, Some(1)))
     );
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 4, Some(1)))
+        .unwrap();
+}
+
+#[test]
+fn deserialized_tx_ids() {
+    let mut engine = Engine::default();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    let json = serde_json::to_string(&engine).unwrap();
+    let mut engine: Engine = serde_json::from_str(&json).unwrap();
+    // transaction IDs recorded before serializing stay unique across clients
+    assert_eq!(
+        Err(TransactionError::DuplicateTransaction(1)),
+        engine.handle_tx(tx(TransactionType::Deposit, 2, 1, Some(10)))
+    );
+}
+
+#[test]
+fn per_client_tx_ids() {
+    let mut engine = Engine::new(DefaultPolicy {
+        per_client_tx_ids: true,
+        ..DefaultPolicy::default()
+    });
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(10)))
+        .unwrap();
+    engine
+        .handle_tx(tx(TransactionType::Deposit, 2, 1, Some(5)))
+        .unwrap();
+    engine
+        .handle_tx(tx(TransactionType::Dispute, 2, 1, None))
+        .unwrap();
+    assert_eq!(Cents::new(5), engine.clients[&2].balance("").held());
+    // other clients' transactions are unknown rather than someone else's
+    assert_eq!(
+        Err(TransactionError::TransactionDoesNotExist(1)),
+        engine.handle_tx(tx(TransactionType::Dispute, 3, 1, None))
+    );
+}
+
+#[test]
+fn partial_dispute_precision() {
+    let mut engine = Engine::default();
+    engine.set_assets(
+        AssetConfig::new(Precision::default(), vec!["USD=2".parse().unwrap()]).unwrap(),
+    );
+    engine
+        .handle_tx(Transaction {
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Deposit, 1, 1, Some(100000))
+        })
+        .unwrap();
+    engine
+        .handle_tx(Transaction {
+            asset: "USD".to_owned(),
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 2, Some(50000))
+        })
+        .unwrap();
+    // checked against the disputed transaction's asset, not the row's empty asset column
+    for (client, id) in [(1, 1), (2, 2), (1, 2)] {
+        for transaction_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ] {
+            assert_eq!(
+                Err(TransactionError::PrecisionExceeded {
+                    asset: "USD".to_owned(),
+                    decimal_places: 2,
+                }),
+                engine.handle_tx(tx(transaction_type, client, id, Some(12345)))
+            );
+        }
+    }
+    engine
+        .handle_tx(tx(TransactionType::Dispute, 1, 1, Some(12300)))
+        .unwrap();
+    assert_eq!(
+        "client,asset,available,held,total,locked,status\n\
+         1,USD,3.77,1.23,5.00,false,active\n\
+         2,USD,5.00,0.00,5.00,false,active",
+        engine.to_string()
+    );
+}
+
+#[test]
+fn capture_precision() {
+    let mut engine = Engine::default();
+    engine.set_assets(
+        AssetConfig::new(Precision::default(), vec!["USD=2".parse().unwrap()]).unwrap(),
+    );
+    engine
+        .handle_tx(Transaction {
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Deposit, 1, 1, Some(100000))
+        })
+        .unwrap();
+    engine
+        .handle_tx(Transaction {
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Authorize, 1, 2, Some(50000))
+        })
+        .unwrap();
+    // checked against the authorization's asset, not the row's empty asset column
+    assert_eq!(
+        Err(TransactionError::PrecisionExceeded {
+            asset: "USD".to_owned(),
+            decimal_places: 2,
+        }),
+        engine.handle_tx(tx(TransactionType::Capture, 1, 2, Some(12345)))
+    );
+    engine
+        .handle_tx(tx(TransactionType::Capture, 1, 2, Some(12300)))
+        .unwrap();
+    assert_eq!(
+        "client,asset,available,held,total,locked,status\n\
+         1,USD,5.00,3.77,8.77,false,active",
+        engine.to_string()
+    );
+}
+
+#[test]
+fn transfer_chargeback_after_freeze() {
+    let mut engine = Engine::default();
+    let partial = |transaction_type, amount| tx(transaction_type, 2, 2, Some(amount));
+    for tx in [
+        tx(TransactionType::Deposit, 1, 1, Some(10)),
+        Transaction {
+            to: Some(2),
+            ..tx(TransactionType::Transfer, 1, 2, Some(10))
+        },
+        tx(TransactionType::Dispute, 2, 2, None),
+        partial(TransactionType::Chargeback, 4),
+        // the destination was frozen by the first chargeback of this same dispute
+        partial(TransactionType::Chargeback, 6),
+    ] {
+        engine.handle_tx(tx).unwrap();
+    }
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0010,0.0000,0.0010,false,active\n\
+         2,0.0000,0.0000,0.0000,true,locked",
+        engine.to_string()
+    );
+}
+
+#[test]
+fn engines_at_different_precisions() {
+    let mut fiat = Engine::default();
+    fiat.set_assets(AssetConfig::new(Precision::new(2).unwrap(), vec![]).unwrap());
+    let mut default = Engine::default();
+    let withdrawal = tx(TransactionType::Withdrawal, 1, 2, Some(6));
+    for (engine, expected) in [
+        (&mut fiat, "Available: 0.05. Requested withdrawal: 0.06"),
+        (
+            &mut default,
+            "Available: 0.0005. Requested withdrawal: 0.0006",
+        ),
+    ] {
+        engine
+            .handle_tx(tx(TransactionType::Deposit, 1, 1, Some(5)))
+            .unwrap();
+        let err = engine.handle_tx(withdrawal.clone()).unwrap_err();
+        assert!(err.to_string().ends_with(expected));
+    }
 }
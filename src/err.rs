@@ -1,14 +1,148 @@
 //! Error types
 
+use serde::de::DeserializeOwned;
+use std::io;
+use std::num::ParseIntError;
 use thiserror::Error;
 
-use crate::cents::Cents;
-use crate::transaction::{TransactionId, TransactionType};
+use crate::asset::AssetId;
+use crate::cents::{Amount, Cents};
+use crate::client::ClientId;
+use crate::transaction::{Timestamp, TransactionId, TransactionType};
 
+/// Errors from parsing a decimal string into `Cents`
 #[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum CentsError {
+    #[error("Too many decimal places: {found}. Max {max}")]
+    TooManyDecimalPlaces { found: usize, max: usize },
+
+    #[error("Overflow error: {0}")]
+    Overflow(String),
+
+    #[error("{0}")]
+    Malformed(#[from] ParseIntError),
+}
+
+/// Errors from arithmetic on `Cents`
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum ArithmeticError {
+    #[error("Overflow: result exceeds the maximum amount")]
+    Overflow,
+
+    #[error("Underflow: result is negative")]
+    Underflow,
+
+    #[error("Division by zero")]
+    DivisionByZero,
+}
+
+/// Errors from reading and deserializing a row of the input
+#[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ParseError {
+    #[error("Invalid transaction type: {0}")]
+    InvalidType(String),
+
+    #[error("Invalid client ID: {0}")]
+    InvalidClient(String),
+
+    #[error("Invalid transaction ID: {0}")]
+    InvalidTransactionId(String),
+
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(String),
+
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+
+    #[error("Malformed row: {0}")]
+    MalformedRow(String),
+
+    #[error("Invalid header: {0}")]
+    InvalidHeader(String),
+
+    #[error("Failed to read input: {0}")]
+    Io(String),
+}
+
+impl ParseError {
+    /// Name of the error variant, for machine-readable reporting
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidType(_) => "InvalidType",
+            Self::InvalidClient(_) => "InvalidClient",
+            Self::InvalidTransactionId(_) => "InvalidTransactionId",
+            Self::InvalidAmount(_) => "InvalidAmount",
+            Self::InvalidTimestamp(_) => "InvalidTimestamp",
+            Self::MalformedRow(_) => "MalformedRow",
+            Self::InvalidHeader(_) => "InvalidHeader",
+            Self::Io(_) => "Io",
+        }
+    }
+
+    /// Whether the rest of the input can still be read after this error
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, Self::Io(_) | Self::InvalidHeader(_))
+    }
+
+    /// Classifies an error from deserializing `record` into a `TransactionRow`.
+    /// Amounts are only parsed afterwards, see `TransactionRow::parse()`
+    pub(crate) fn from_record(record: &csv::StringRecord, err: csv::Error) -> Self {
+        let de_err = match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err,
+            _ => return err.into(),
+        };
+        let msg = de_err.kind().to_string();
+        // errors raised by custom deserializers do not carry the field index
+        let field = de_err.field().or_else(|| first_invalid_column(record));
+        // field indices follow the column order of `TransactionRow`
+        match field {
+            Some(0) => Self::InvalidType(msg),
+            Some(1) => Self::InvalidClient(msg),
+            Some(2) => Self::InvalidTransactionId(msg),
+            Some(6) => Self::InvalidTimestamp(msg),
+            _ => Self::MalformedRow(msg),
+        }
+    }
+}
+
+impl From<csv::Error> for ParseError {
+    fn from(err: csv::Error) -> Self {
+        match err.kind() {
+            csv::ErrorKind::Io(io_err) => Self::Io(io_err.to_string()),
+            _ => Self::MalformedRow(err.to_string()),
+        }
+    }
+}
+
+/// Index of the first column of `record` that does not deserialize into its `TransactionRow` field
+fn first_invalid_column(record: &csv::StringRecord) -> Option<u64> {
+    fn is_invalid<T: DeserializeOwned>(record: &csv::StringRecord, i: usize) -> bool {
+        let field = csv::StringRecord::from(vec![record.get(i).unwrap_or("")]);
+        field.deserialize::<T>(None).is_err()
+    }
+    if is_invalid::<TransactionType>(record, 0) {
+        Some(0)
+    } else if is_invalid::<ClientId>(record, 1) {
+        Some(1)
+    } else if is_invalid::<TransactionId>(record, 2) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Reasons the engine rejects a transaction
+#[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum TransactionError {
     #[error("Insufficient Balance. Available: {available}. Requested withdrawal: {requested}")]
-    InsufficientBalance { available: Cents, requested: Cents },
+    InsufficientBalance {
+        available: Amount,
+        requested: Amount,
+    },
 
     #[error("Transaction {0} already exists")]
     DuplicateTransaction(TransactionId),
@@ -28,6 +162,167 @@ pub enum TransactionError {
     #[error("No amount specified for transaction")]
     NoAmount,
 
+    #[error("Negative amount for transaction type {0:?}. Only adjustments can be negative")]
+    NegativeAmount(TransactionType),
+
     #[error("Account frozen")]
     AccountFrozen,
+
+    #[error("Account not locked")]
+    AccountNotLocked,
+
+    #[error("Account closed")]
+    AccountClosed,
+
+    #[error("Cannot close account with {held} held in asset {asset:?}")]
+    HeldFundsOnClose { asset: AssetId, held: Amount },
+
+    #[error("Amount has more decimal places than {decimal_places} allowed for asset {asset:?}")]
+    PrecisionExceeded {
+        asset: AssetId,
+        decimal_places: usize,
+    },
+
+    #[error("Transaction {tx} has already been disputed the maximum of {max_disputes} times")]
+    DisputeLimitReached {
+        tx: TransactionId,
+        max_disputes: usize,
+    },
+
+    #[error("Cannot dispute {requested} of transaction {tx}, only {undisputed} is undisputed")]
+    DisputeExceedsAmount {
+        tx: TransactionId,
+        requested: Amount,
+        undisputed: Amount,
+    },
+
+    #[error("Cannot settle {requested} of transaction {tx}, only {disputed} is under dispute")]
+    ExceedsDisputedAmount {
+        tx: TransactionId,
+        requested: Amount,
+        disputed: Amount,
+    },
+
+    #[error("No destination client specified for transfer")]
+    NoDestination,
+
+    #[error("Cannot transfer to the same client")]
+    TransferToSelf,
+
+    #[error("Balance would exceed the maximum amount of {} cents", Cents::MAX.value())]
+    Overflow,
+
+    #[error("Transaction {0} is under dispute")]
+    TransactionUnderDispute(TransactionId),
+
+    #[error("Transaction {0} already reversed")]
+    AlreadyReversed(TransactionId),
+
+    #[error("Transaction {0} cannot be reversed")]
+    TransactionIrreversible(TransactionId),
+
+    #[error("Authorization {0} does not exist")]
+    UnknownAuthorization(TransactionId),
+
+    #[error("Authorization {0} already voided")]
+    AuthorizationVoided(TransactionId),
+
+    #[error("Authorization {0} expired")]
+    AuthorizationExpired(TransactionId),
+
+    #[error("Authorization {0} already fully captured")]
+    AuthorizationCaptured(TransactionId),
+
+    #[error("Cannot capture {requested} of authorization {tx}, only {remaining} is held")]
+    ExceedsAuthorizedAmount {
+        tx: TransactionId,
+        requested: Amount,
+        remaining: Amount,
+    },
+
+    #[error("Timestamp {timestamp} is before the client's previous transaction at {last}")]
+    TimestampOutOfOrder {
+        timestamp: Timestamp,
+        last: Timestamp,
+    },
+
+    #[error("Transaction {tx} can no longer be disputed, its {window}s dispute window has passed")]
+    DisputeWindowExpired { tx: TransactionId, window: u64 },
+
+    #[error("Transaction {tx} belongs to client {owner}")]
+    TransactionBelongsToOtherClient { tx: TransactionId, owner: ClientId },
+}
+
+impl TransactionError {
+    /// Name of the error variant, for machine-readable reporting
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InsufficientBalance { .. } => "InsufficientBalance",
+            Self::DuplicateTransaction(_) => "DuplicateTransaction",
+            Self::TransactionDoesNotExist(_) => "TransactionDoesNotExist",
+            Self::TransactionNotUnderDispute(_) => "TransactionNotUnderDispute",
+            Self::TransactionIndisputable(_) => "TransactionIndisputable",
+            Self::AlreadyChargedBack(_) => "AlreadyChargedBack",
+            Self::NoAmount => "NoAmount",
+            Self::NegativeAmount(_) => "NegativeAmount",
+            Self::AccountFrozen => "AccountFrozen",
+            Self::AccountNotLocked => "AccountNotLocked",
+            Self::AccountClosed => "AccountClosed",
+            Self::HeldFundsOnClose { .. } => "HeldFundsOnClose",
+            Self::PrecisionExceeded { .. } => "PrecisionExceeded",
+            Self::DisputeLimitReached { .. } => "DisputeLimitReached",
+            Self::DisputeExceedsAmount { .. } => "DisputeExceedsAmount",
+            Self::ExceedsDisputedAmount { .. } => "ExceedsDisputedAmount",
+            Self::NoDestination => "NoDestination",
+            Self::TransferToSelf => "TransferToSelf",
+            Self::Overflow => "Overflow",
+            Self::TransactionUnderDispute(_) => "TransactionUnderDispute",
+            Self::AlreadyReversed(_) => "AlreadyReversed",
+            Self::TransactionIrreversible(_) => "TransactionIrreversible",
+            Self::UnknownAuthorization(_) => "UnknownAuthorization",
+            Self::AuthorizationVoided(_) => "AuthorizationVoided",
+            Self::AuthorizationExpired(_) => "AuthorizationExpired",
+            Self::AuthorizationCaptured(_) => "AuthorizationCaptured",
+            Self::ExceedsAuthorizedAmount { .. } => "ExceedsAuthorizedAmount",
+            Self::TimestampOutOfOrder { .. } => "TimestampOutOfOrder",
+            Self::DisputeWindowExpired { .. } => "DisputeWindowExpired",
+            Self::TransactionBelongsToOtherClient { .. } => "TransactionBelongsToOtherClient",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Failed to access snapshot: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Malformed snapshot: {0}")]
+    Format(#[from] serde_json::Error),
+
+    #[error("Unsupported snapshot version {found}. Expected version {expected}")]
+    UnsupportedVersion { found: u32, expected: u32 },
+
+    #[error("Snapshot has {found} decimal places of precision. Expected {expected}")]
+    PrecisionMismatch { found: usize, expected: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Failed to access journal: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to serialize journal entry: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("Malformed journal entry after {entry} transactions: {source}")]
+    Format {
+        entry: u64,
+        source: serde_json::Error,
+    },
+
+    #[error("Invalid amount in journal entry after {entry} transactions: {source}")]
+    Amount { entry: u64, source: CentsError },
+
+    #[error("Replaying journal entry {0} did not reproduce its recorded outcome")]
+    Diverged(u64),
 }
@@ -0,0 +1,99 @@
+//! Mapping of the named columns of the input onto the columns of a transaction
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Names of the columns of a transaction, in the order `TransactionRow` deserializes them
+pub const COLUMNS: [&str; 7] = ["type", "client", "tx", "amount", "asset", "to", "timestamp"];
+
+/// Columns every CSV header must have. The rest are optional
+pub const REQUIRED_COLUMNS: usize = 4;
+
+/// Another name for a column, e.g. `client_id=client`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnAlias {
+    pub alias: String,
+    pub column: String,
+}
+
+impl FromStr for ColumnAlias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (alias, column) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <alias>=<column>, got {}", s))?;
+        Ok(Self {
+            alias: alias.to_owned(),
+            column: column.to_owned(),
+        })
+    }
+}
+
+/// Resolves the name of an input column to the index of a transaction column in `COLUMNS`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnMapping {
+    aliases: HashMap<String, usize>,
+    /// Whether columns that are neither a transaction column nor an alias are ignored,
+    /// rather than rejected
+    ignore_unknown: bool,
+}
+
+impl ColumnMapping {
+    pub fn new(aliases: Vec<ColumnAlias>, ignore_unknown: bool) -> Result<Self, String> {
+        let mut indices = HashMap::new();
+        for a in aliases {
+            let index = COLUMNS
+                .iter()
+                .position(|column| *column == a.column)
+                .ok_or_else(|| format!("Unknown column for alias {}: {}", a.alias, a.column))?;
+            if COLUMNS.contains(&a.alias.as_str()) {
+                return Err(format!("Alias is already a column: {}", a.alias));
+            }
+            indices.insert(a.alias, index);
+        }
+        Ok(Self {
+            aliases: indices,
+            ignore_unknown,
+        })
+    }
+
+    /// Index of the transaction column named `name`, or that `name` is an alias of
+    pub fn column(&self, name: &str) -> Option<usize> {
+        COLUMNS
+            .iter()
+            .position(|column| *column == name)
+            .or_else(|| self.aliases.get(name).copied())
+    }
+
+    /// Where each transaction column is among the input columns `names`, if it is there at all
+    pub fn positions<'a, I>(&self, names: I) -> Result<Vec<Option<usize>>, String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut positions = vec![None; COLUMNS.len()];
+        for (i, name) in names.into_iter().enumerate() {
+            let index = match self.column(name) {
+                Some(index) => index,
+                None if self.ignore_unknown => continue,
+                None => return Err(format!("Unknown column: {}", name)),
+            };
+            if positions[index].replace(i).is_some() {
+                return Err(format!("Duplicate column: {}", COLUMNS[index]));
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Like `positions()`, but also checks that the header has all required columns
+    pub fn header_positions(
+        &self,
+        header: &csv::StringRecord,
+    ) -> Result<Vec<Option<usize>>, String> {
+        let positions = self.positions(header)?;
+        if let Some(missing) = (0..REQUIRED_COLUMNS).find(|&index| positions[index].is_none()) {
+            return Err(format!("Missing required column: {}", COLUMNS[missing]));
+        }
+        Ok(positions)
+    }
+}
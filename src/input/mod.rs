@@ -0,0 +1,312 @@
+//! Readers decoding transactions from the supported input formats.
+//!
+//! Every format is decoded into the same `csv::StringRecord` of raw columns in `COLUMNS` order
+//! before being deserialized into a `Transaction`, so that all formats share the validation
+//! and error reporting of the CSV path. Amounts are parsed at the `Precision` the readers are
+//! given, which should be that of the engine the transactions are for.
+
+pub mod columns;
+#[cfg(test)]
+mod test;
+
+pub use columns::{ColumnAlias, ColumnMapping, COLUMNS};
+
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::value::RawValue;
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cents::Precision;
+use crate::err::ParseError;
+use crate::transaction::{Transaction, TransactionRow};
+
+/// Format of the input transactions
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum InputFormat {
+    /// A header row followed by one row per transaction
+    #[default]
+    Csv,
+    /// One JSON object per line, keyed by column name
+    JsonLines,
+}
+
+impl InputFormat {
+    /// The format implied by the extension of `path`, if any
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            _ => Err(format!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+/// A row that could not be parsed into a transaction, along with the raw columns
+/// if they could be read at all
+#[derive(Debug, PartialEq)]
+pub struct InvalidRow {
+    pub line: u64,
+    pub record: Option<csv::StringRecord>,
+    pub err: ParseError,
+}
+
+/// A transaction along with its line number, or the row that could not be parsed
+pub type Row = Result<(u64, Transaction), InvalidRow>;
+
+/// Reads the rows of `rdr` in the given format, naming columns as in `columns`
+pub fn rows<'a, R: io::Read + 'a>(
+    format: InputFormat,
+    columns: ColumnMapping,
+    precision: Precision,
+    rdr: R,
+) -> Box<dyn Iterator<Item = Row> + 'a> {
+    match format {
+        InputFormat::Csv => Box::new(CsvRows::new(rdr, columns, precision)),
+        InputFormat::JsonLines => Box::new(JsonLinesRows::new(rdr, columns, precision)),
+    }
+}
+
+/// The raw columns in `COLUMNS` order, given where each one is among the input's fields.
+/// Missing columns are empty
+fn select<'a, F>(positions: &[Option<usize>], field: F) -> csv::StringRecord
+where
+    F: Fn(usize) -> Option<&'a str>,
+{
+    positions
+        .iter()
+        .map(|position| position.and_then(&field).unwrap_or(""))
+        .collect()
+}
+
+/// Deserializes the raw columns of a row into a transaction with amounts at `precision`
+fn parse_record(line: u64, record: csv::StringRecord, precision: Precision) -> Row {
+    let parsed = record
+        .deserialize::<TransactionRow>(None)
+        .map_err(|err| ParseError::from_record(&record, err))
+        .and_then(|row| row.parse(precision));
+    match parsed {
+        Ok(tx) => Ok((line, tx)),
+        Err(err) => Err(InvalidRow {
+            line,
+            record: Some(record),
+            err,
+        }),
+    }
+}
+
+/// Columns are matched to the transaction's by the header, so they can be in any order
+pub struct CsvRows<R: io::Read> {
+    rdr: csv::Reader<R>,
+    columns: ColumnMapping,
+    precision: Precision,
+    /// Where each column of `COLUMNS` is in the header, once it has been read
+    positions: Option<Vec<Option<usize>>>,
+    /// Set once the header turned out to be invalid, after which no rows are read
+    failed: bool,
+}
+
+impl<R: io::Read> CsvRows<R> {
+    pub fn new(rdr: R, columns: ColumnMapping, precision: Precision) -> Self {
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr);
+        Self {
+            rdr,
+            columns,
+            precision,
+            positions: None,
+            failed: false,
+        }
+    }
+
+    /// Reads the header, unless it already has been
+    fn read_header(&mut self) -> Result<(), InvalidRow> {
+        if self.positions.is_some() {
+            return Ok(());
+        }
+        let header = self.rdr.headers().map_err(|err| InvalidRow {
+            line: err.position().map_or(1, |pos| pos.line()),
+            record: None,
+            err: err.into(),
+        })?;
+        // an empty input has no header, nor any rows
+        if header.is_empty() {
+            self.positions = Some(Vec::new());
+            return Ok(());
+        }
+        let positions = self
+            .columns
+            .header_positions(header)
+            .map_err(|msg| InvalidRow {
+                line: header.position().map_or(1, |pos| pos.line()),
+                record: None,
+                err: ParseError::InvalidHeader(msg),
+            })?;
+        self.positions = Some(positions);
+        Ok(())
+    }
+}
+
+impl<R: io::Read> Iterator for CsvRows<R> {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        if self.failed {
+            return None;
+        }
+        if let Err(invalid) = self.read_header() {
+            self.failed = true;
+            return Some(Err(invalid));
+        }
+        let mut record = csv::StringRecord::new();
+        match self.rdr.read_record(&mut record) {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(err) => {
+                let line = err.position().map_or(0, |pos| pos.line());
+                return Some(Err(InvalidRow {
+                    line,
+                    record: None,
+                    err: err.into(),
+                }));
+            }
+        }
+        let line = record.position().map_or(0, |pos| pos.line());
+        // unwrap safety: the header has been read above
+        let positions = self.positions.as_ref().unwrap();
+        let record = select(positions, |i| record.get(i));
+        Some(parse_record(line, record, self.precision))
+    }
+}
+
+/// Blank lines are skipped. Numbers are taken as written rather than parsed as floats,
+/// so amounts are exact whether given as strings or numbers
+pub struct JsonLinesRows<R: io::Read> {
+    rdr: BufReader<R>,
+    columns: ColumnMapping,
+    precision: Precision,
+    line: u64,
+    /// Set once reading failed, after which no further rows are read
+    failed: bool,
+}
+
+impl<R: io::Read> JsonLinesRows<R> {
+    pub fn new(rdr: R, columns: ColumnMapping, precision: Precision) -> Self {
+        Self {
+            rdr: BufReader::new(rdr),
+            columns,
+            precision,
+            line: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for JsonLinesRows<R> {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        let mut buf = Vec::new();
+        loop {
+            if self.failed {
+                return None;
+            }
+            buf.clear();
+            self.line += 1;
+            match self.rdr.read_until(b'\n', &mut buf) {
+                Ok(0) => return None,
+                Ok(_) => (),
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(InvalidRow {
+                        line: self.line,
+                        record: None,
+                        err: ParseError::Io(err.to_string()),
+                    }));
+                }
+            }
+            if !buf.iter().all(u8::is_ascii_whitespace) {
+                break;
+            }
+        }
+        while buf.last().is_some_and(u8::is_ascii_whitespace) {
+            buf.pop();
+        }
+        let record = match json_record(&buf, &self.columns) {
+            Ok(record) => record,
+            Err(err) => {
+                return Some(Err(InvalidRow {
+                    line: self.line,
+                    record: None,
+                    err,
+                }))
+            }
+        };
+        Some(parse_record(self.line, record, self.precision))
+    }
+}
+
+/// The members of a JSON object in the order they were written, keeping duplicate keys
+/// so that they can be rejected like duplicate CSV columns
+struct JsonObject<'a>(Vec<(String, &'a RawValue)>);
+
+impl<'de: 'a, 'a> Deserialize<'de> for JsonObject<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct JsonObjectVisitor;
+
+        impl<'de> Visitor<'de> for JsonObjectVisitor {
+            type Value = JsonObject<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a JSON object")
+            }
+
+            fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+            where
+                M: MapAccess<'de>,
+            {
+                let mut members = Vec::new();
+                while let Some(member) = map.next_entry()? {
+                    members.push(member);
+                }
+                Ok(JsonObject(members))
+            }
+        }
+
+        deserializer.deserialize_map(JsonObjectVisitor)
+    }
+}
+
+/// The raw columns of a JSON object, keyed by column name. Strings are unescaped,
+/// `null` and missing keys are empty and anything else is kept as written
+fn json_record(line: &[u8], columns: &ColumnMapping) -> Result<csv::StringRecord, ParseError> {
+    let malformed = |err: serde_json::Error| ParseError::MalformedRow(err.to_string());
+    let JsonObject(members) = serde_json::from_slice(line).map_err(malformed)?;
+    let positions = columns
+        .positions(members.iter().map(|(key, _)| key.as_str()))
+        .map_err(ParseError::MalformedRow)?;
+    let mut fields = Vec::with_capacity(members.len());
+    for raw in members.iter().map(|(_, value)| value.get()) {
+        let field = match raw {
+            "null" => String::new(),
+            _ if raw.starts_with('"') => serde_json::from_str(raw).map_err(malformed)?,
+            _ => raw.to_owned(),
+        };
+        fields.push(field);
+    }
+    Ok(select(&positions, |i| Some(fields[i].trim())))
+}
@@ -0,0 +1,262 @@
+use crate::cents::{Cents, Precision};
+use crate::transaction::TransactionType;
+
+use super::*;
+
+fn kinds(rows: Vec<Row>) -> Vec<Result<u64, (u64, &'static str)>> {
+    rows.into_iter()
+        .map(|row| match row {
+            Ok((line, _)) => Ok(line),
+            Err(invalid) => Err((invalid.line, invalid.err.kind())),
+        })
+        .collect()
+}
+
+#[test]
+fn format() {
+    assert_eq!(Ok(InputFormat::Csv), "csv".parse());
+    assert_eq!(Ok(InputFormat::JsonLines), "jsonl".parse());
+    assert_eq!(Ok(InputFormat::JsonLines), "ndjson".parse());
+    assert!("json".parse::<InputFormat>().is_err());
+    assert_eq!(
+        Some(InputFormat::JsonLines),
+        InputFormat::from_path("in/tx.ndjson")
+    );
+    assert_eq!(Some(InputFormat::Csv), InputFormat::from_path("tx.csv"));
+    assert_eq!(None, InputFormat::from_path("tx.txt"));
+    assert_eq!(None, InputFormat::from_path("-"));
+}
+
+#[test]
+fn csv() {
+    let input = "type,client,tx,amount\n\
+                 deposit, 1, 1, 1.5\n\
+                 deposit,1,x,1.5\n\
+                 dispute,1,1,\n";
+    let rows: Vec<Row> = rows(
+        InputFormat::Csv,
+        ColumnMapping::default(),
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .collect();
+    assert_eq!(
+        vec![Ok(2), Err((3, "InvalidTransactionId")), Ok(4)],
+        kinds(rows)
+    );
+}
+
+#[test]
+fn json_lines() {
+    let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 0.1}
+{"type":"deposit","client":1,"tx":2,"amount":"1844674407370955.1615","asset":"USD"}
+
+{"type": "adjustment", "client": 1, "tx": 3, "amount": -2, "timestamp": 1700000000}
+{"type": "dispute", "client": 1, "tx": 1, "amount": null}
+"#;
+    let rows: Vec<Transaction> = rows(
+        InputFormat::JsonLines,
+        ColumnMapping::default(),
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .map(|row| row.unwrap().1)
+    .collect();
+    let deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(Cents::new(1000)));
+    let mut large = Transaction::new(TransactionType::Deposit, 1, 2, Some(Cents::MAX));
+    large.asset = "USD".to_owned();
+    let mut adjustment =
+        Transaction::new(TransactionType::Adjustment, 1, 3, Some(Cents::new(20000)));
+    adjustment.negative = true;
+    adjustment.timestamp = Some(1700000000);
+    assert_eq!(
+        vec![
+            deposit,
+            large,
+            adjustment,
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ],
+        rows
+    );
+}
+
+#[test]
+fn json_lines_errors() {
+    let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1e3}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 0.00001}
+{"type": "withdrawal", "client": 1, "tx": 3, "amount": -1}
+{"type": "deposit", "client": 1.0, "tx": 4, "amount": 1}
+{"type": "deposit", "tx": 5, "amount": 1}
+{"type": "deposit", "client": 1, "tx": 6, "amount": 1, "memo": "x"}
+{"type": "deposit", "client": 1, "tx": 8, "amount": 3, "amount": 4}
+{"type": "deposit", "client": 1, "tx": 7,
+"#;
+    let rows: Vec<Row> = rows(
+        InputFormat::JsonLines,
+        ColumnMapping::default(),
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .collect();
+    assert_eq!(
+        vec![
+            Err((1, "InvalidAmount")),
+            Err((2, "InvalidAmount")),
+            Err((3, "InvalidAmount")),
+            Err((4, "InvalidClient")),
+            Err((5, "InvalidClient")),
+            Err((6, "MalformedRow")),
+            Err((7, "MalformedRow")),
+            Err((8, "MalformedRow")),
+        ],
+        kinds(rows)
+    );
+}
+
+#[test]
+fn json_lines_record() {
+    // rejected rows keep the raw columns they could be read into, for reporting
+    let input = r#"{"type": "deposit", "client": 7, "tx": 9, "amount": "abc"}"#;
+    let row = rows(
+        InputFormat::JsonLines,
+        ColumnMapping::default(),
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .next()
+    .unwrap();
+    assert_eq!(
+        Err(InvalidRow {
+            line: 1,
+            record: Some(csv::StringRecord::from(vec![
+                "deposit", "7", "9", "abc", "", "", ""
+            ])),
+            err: ParseError::InvalidAmount("invalid digit found in string".to_owned()),
+        }),
+        row
+    );
+}
+
+#[test]
+fn parse_alias() {
+    assert_eq!(
+        Ok(ColumnAlias {
+            alias: "client_id".to_owned(),
+            column: "client".to_owned(),
+        }),
+        "client_id=client".parse()
+    );
+    assert!("client_id".parse::<ColumnAlias>().is_err());
+    assert!(ColumnMapping::new(vec!["id=account".parse().unwrap()], false).is_err());
+    assert!(ColumnMapping::new(vec!["client=tx".parse().unwrap()], false).is_err());
+}
+
+#[test]
+fn csv_by_header() {
+    let columns = ColumnMapping::new(
+        vec![
+            "client_id=client".parse().unwrap(),
+            "txid=tx".parse().unwrap(),
+            "value=amount".parse().unwrap(),
+        ],
+        true,
+    )
+    .unwrap();
+    let input = "value,memo,txid,type,client_id\n\
+                 1.5,first,1,deposit,2\n";
+    let rows: Vec<Transaction> = rows(
+        InputFormat::Csv,
+        columns,
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .map(|row| row.unwrap().1)
+    .collect();
+    assert_eq!(
+        vec![Transaction::new(
+            TransactionType::Deposit,
+            2,
+            1,
+            Some(Cents::new(15000))
+        )],
+        rows
+    );
+}
+
+#[test]
+fn csv_invalid_header() {
+    let cases = [
+        ("type,client,tx\n", "Missing required column: amount"),
+        ("type,client,tx,amount,memo\n", "Unknown column: memo"),
+        ("type,client,tx,amount,txid\n", "Duplicate column: tx"),
+    ];
+    let columns = ColumnMapping::new(vec!["txid=tx".parse().unwrap()], false).unwrap();
+    for (header, msg) in cases.iter() {
+        let input = format!("{}deposit,1,1,1,1\n", header);
+        let rows: Vec<Row> = rows(
+            InputFormat::Csv,
+            columns.clone(),
+            Precision::default(),
+            input.as_bytes(),
+        )
+        .collect();
+        assert_eq!(
+            vec![Err(InvalidRow {
+                line: 1,
+                record: None,
+                err: ParseError::InvalidHeader(msg.to_string()),
+            })],
+            rows
+        );
+    }
+    // an empty input has no header to check
+    assert_eq!(
+        0,
+        rows(
+            InputFormat::Csv,
+            ColumnMapping::default(),
+            Precision::default(),
+            "".as_bytes()
+        )
+        .count()
+    );
+}
+
+#[test]
+fn json_lines_aliases() {
+    let columns = ColumnMapping::new(vec!["value=amount".parse().unwrap()], true).unwrap();
+    let input = r#"{"type": "deposit", "client": 1, "tx": 1, "value": 1, "memo": "x"}
+{"type": "deposit", "client": 1, "tx": 2, "value": 1, "amount": 1}
+"#;
+    let rows: Vec<Row> = rows(
+        InputFormat::JsonLines,
+        columns,
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .collect();
+    // the second row has the amount twice
+    assert_eq!(vec![Ok(1), Err((2, "MalformedRow"))], kinds(rows));
+}
+
+#[test]
+fn json_lines_duplicate_key() {
+    // rejected like a duplicate csv column, rather than the last value winning
+    let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 3, "amount": 4}"#;
+    let row = rows(
+        InputFormat::JsonLines,
+        ColumnMapping::default(),
+        Precision::default(),
+        input.as_bytes(),
+    )
+    .next()
+    .unwrap();
+    assert_eq!(
+        Err(InvalidRow {
+            line: 1,
+            record: None,
+            err: ParseError::MalformedRow("Duplicate column: amount".to_owned()),
+        }),
+        row
+    );
+}
@@ -0,0 +1,285 @@
+//! Append-only write-ahead journal of transactions fed to the engine.
+//!
+//! Each transaction is written to the journal before the engine applies it,
+//! followed by its outcome once applied. Since the engine is deterministic,
+//! replaying the journaled transactions rebuilds the engine state after a crash.
+
+#[cfg(test)]
+mod test;
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::asset::AssetId;
+use crate::cents::Precision;
+use crate::client::ClientId;
+use crate::engine::Engine;
+use crate::err::{CentsError, JournalError, TransactionError};
+use crate::report::Rejected;
+use crate::transaction::{Timestamp, Transaction, TransactionId, TransactionType};
+
+/// When journal entries are fsynced to disk.
+/// Entries are always handed to the OS before the engine applies the transaction,
+/// so they survive a crash of the process regardless of policy
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FsyncPolicy {
+    /// After every entry
+    Always,
+    /// After every n transactions
+    Every(u64),
+    /// Leave it to the OS
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => match s.strip_prefix("every=").map(str::parse) {
+                Some(Ok(n)) if n > 0 => Ok(Self::Every(n)),
+                _ => Err(format!(
+                    "Unknown fsync policy: {}. Expected always, never or every=<n>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Amounts are written as decimals at the engine's precision
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct JournaledTransaction {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<String>,
+    #[serde(default)]
+    asset: AssetId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to: Option<ClientId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
+}
+
+impl JournaledTransaction {
+    fn new(tx: &Transaction, precision: Precision) -> Self {
+        Self {
+            transaction_type: tx.transaction_type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx
+                .signed_amount()
+                .map(|amount| precision.amount(amount).to_string()),
+            asset: tx.asset.clone(),
+            to: tx.to,
+            timestamp: tx.timestamp,
+        }
+    }
+
+    fn parse(self, precision: Precision) -> Result<Transaction, CentsError> {
+        let amount = self
+            .amount
+            .map(|amount| precision.parse_signed(&amount))
+            .transpose()?;
+        Ok(Transaction {
+            transaction_type: self.transaction_type,
+            client: self.client,
+            tx: self.tx,
+            amount: amount.map(|amount| amount.magnitude()),
+            asset: self.asset,
+            to: self.to,
+            negative: amount.is_some_and(|amount| amount.is_negative()),
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "entry", rename_all = "lowercase")]
+enum Entry {
+    /// Written before the transaction is applied
+    Transaction {
+        seq: u64,
+        line: u64,
+        tx: JournaledTransaction,
+    },
+    /// Written after the transaction is applied
+    Outcome { seq: u64, error: Option<String> },
+}
+
+/// What was rebuilt from an existing journal
+#[derive(Debug, Default, PartialEq)]
+pub struct Replay {
+    /// Number of transactions replayed
+    pub transactions: u64,
+    /// Input line number of the last journaled transaction,
+    /// rows up to and including this line have already been processed
+    pub last_line: Option<u64>,
+    /// Replayed transactions that were rejected, in order of input line number
+    pub rejected: Vec<Rejected>,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    policy: FsyncPolicy,
+    /// Of the engine the journaled transactions are applied to
+    precision: Precision,
+    seq: u64,
+    unsynced: u64,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if it doesn't exist,
+    /// and replays any transactions already in it into `engine`.
+    /// A partially written trailing entry left by a crash is discarded
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        policy: FsyncPolicy,
+        engine: &mut Engine,
+    ) -> Result<(Self, Replay), JournalError> {
+        let path = path.as_ref().to_owned();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let (replay, seq, valid_len) = replay(BufReader::new(&mut file), engine)?;
+        file.set_len(valid_len)?;
+        file.seek(SeekFrom::End(0))?;
+        let journal = Self {
+            path,
+            file,
+            policy,
+            precision: engine.precision(),
+            seq,
+            unsynced: 0,
+        };
+        Ok((journal, replay))
+    }
+
+    /// Records a transaction about to be applied, returning its sequence number
+    pub fn record_tx(&mut self, line: u64, tx: &Transaction) -> Result<u64, JournalError> {
+        let seq = self.seq;
+        self.append(&Entry::Transaction {
+            seq,
+            line,
+            tx: JournaledTransaction::new(tx, self.precision),
+        })?;
+        self.seq += 1;
+        Ok(seq)
+    }
+
+    /// Records the outcome of applying the transaction with sequence number `seq`
+    pub fn record_outcome(
+        &mut self,
+        seq: u64,
+        outcome: &Result<(), TransactionError>,
+    ) -> Result<(), JournalError> {
+        self.append(&Entry::Outcome {
+            seq,
+            error: outcome.as_ref().err().map(TransactionError::to_string),
+        })?;
+        self.unsynced += 1;
+        if let FsyncPolicy::Every(n) = self.policy {
+            if self.unsynced >= n {
+                self.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<(), JournalError> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Removes the journal at the end of a run. This must happen before the engine state is
+    /// saved, so that a crash in between never replays the journal onto a state that
+    /// already has its transactions
+    pub fn complete(self) -> Result<(), JournalError> {
+        drop(self.file);
+        fs::remove_file(self.path)?;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<(), JournalError> {
+        let mut buf = serde_json::to_vec(entry)?;
+        buf.push(b'\n');
+        // single write so that each entry is handed to the OS in one piece
+        self.file.write_all(&buf)?;
+        if self.policy == FsyncPolicy::Always {
+            self.sync()?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays all journaled transactions into `engine`, checking that they have the same outcomes.
+/// Returns the replay summary, the next sequence number and the length in bytes of all
+/// complete entries
+fn replay<R: BufRead>(mut rdr: R, engine: &mut Engine) -> Result<(Replay, u64, u64), JournalError> {
+    let mut summary = Replay::default();
+    let mut seq = 0;
+    let mut valid_len = 0;
+    let mut pending = None;
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        let read = rdr.read_line(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        let entry: Entry = match serde_json::from_str(&buf) {
+            Ok(entry) => entry,
+            // partially written trailing entry, the transaction was never applied
+            Err(_) if !buf.ends_with('\n') => break,
+            Err(err) => {
+                return Err(JournalError::Format {
+                    entry: summary.transactions,
+                    source: err,
+                })
+            }
+        };
+        valid_len += read as u64;
+        match entry {
+            Entry::Transaction {
+                seq: entry_seq,
+                line,
+                tx,
+            } => {
+                let tx = tx
+                    .parse(engine.precision())
+                    .map_err(|source| JournalError::Amount {
+                        entry: summary.transactions,
+                        source,
+                    })?;
+                let outcome = engine.handle_tx(tx.clone());
+                pending = Some((entry_seq, outcome.as_ref().err().map(|err| err.to_string())));
+                if let Err(err) = outcome {
+                    summary.rejected.push(Rejected { line, tx, err });
+                }
+                seq = entry_seq + 1;
+                summary.transactions += 1;
+                summary.last_line = Some(line);
+            }
+            Entry::Outcome {
+                seq: entry_seq,
+                error,
+            } => match pending.take() {
+                Some((pending_seq, replayed)) if pending_seq == entry_seq && replayed == error => {}
+                _ => return Err(JournalError::Diverged(entry_seq)),
+            },
+        }
+    }
+    Ok((summary, seq, valid_len))
+}
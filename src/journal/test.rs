@@ -0,0 +1,129 @@
+use std::env;
+
+use crate::engine::Engine;
+use crate::err::JournalError;
+use crate::transaction::{tx, Transaction, TransactionType};
+
+use super::*;
+
+fn journal_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("transactions-journal-test-{}.jsonl", name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn txs() -> Vec<Transaction> {
+    vec![
+        tx(TransactionType::Deposit, 1, 1, Some(100)),
+        tx(TransactionType::Withdrawal, 1, 2, Some(200)),
+        tx(TransactionType::Dispute, 1, 1, None),
+        tx(TransactionType::Deposit, 2, 3, Some(20)),
+    ]
+}
+
+/// Applies `txs` through a journal at `path`, as the CLI does
+fn apply(path: &Path, txs: Vec<Transaction>) -> Engine {
+    let mut engine = Engine::default();
+    let (mut journal, _) = Journal::open(path, FsyncPolicy::Never, &mut engine).unwrap();
+    for (i, tx) in txs.into_iter().enumerate() {
+        let seq = journal.record_tx(i as u64 + 2, &tx).unwrap();
+        let outcome = engine.handle_tx(tx);
+        journal.record_outcome(seq, &outcome).unwrap();
+    }
+    engine
+}
+
+#[test]
+fn replay() {
+    let path = journal_path("replay");
+    let engine = apply(&path, txs());
+
+    let mut replayed = Engine::default();
+    let (journal, replay) = Journal::open(&path, FsyncPolicy::Never, &mut replayed).unwrap();
+    assert_eq!(engine, replayed);
+    assert_eq!(
+        Replay {
+            transactions: 4,
+            last_line: Some(5),
+            rejected: vec![Rejected {
+                line: 3,
+                tx: tx(TransactionType::Withdrawal, 1, 2, Some(200)),
+                err: TransactionError::InsufficientBalance {
+                    available: Precision::default().amount(100),
+                    requested: Precision::default().amount(200),
+                },
+            }],
+        },
+        replay
+    );
+    journal.complete().unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn partial_entry() {
+    let path = journal_path("partial_entry");
+    apply(&path, txs());
+    let complete_len = fs::metadata(&path).unwrap().len();
+    // crashed midway through writing the next entry
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"entry\":\"transaction\",\"seq\":4,\"li")
+        .unwrap();
+    drop(file);
+
+    let mut replayed = Engine::default();
+    let (mut journal, replay) = Journal::open(&path, FsyncPolicy::Never, &mut replayed).unwrap();
+    assert_eq!(4, replay.transactions);
+    assert_eq!(complete_len, fs::metadata(&path).unwrap().len());
+    // appending continues from the last complete entry
+    assert_eq!(
+        4,
+        journal
+            .record_tx(6, &tx(TransactionType::Resolve, 1, 1, None))
+            .unwrap()
+    );
+    drop(journal);
+    let mut replayed = Engine::default();
+    let (_, replay) = Journal::open(&path, FsyncPolicy::Never, &mut replayed).unwrap();
+    assert_eq!(5, replay.transactions);
+    assert_eq!(Some(6), replay.last_line);
+}
+
+#[test]
+fn diverged() {
+    let path = journal_path("diverged");
+    fs::write(
+        &path,
+        "{\"entry\":\"transaction\",\"seq\":0,\"line\":2,\
+         \"tx\":{\"type\":\"withdrawal\",\"client\":1,\"tx\":1,\"amount\":\"0.0001\"}}\n\
+         {\"entry\":\"outcome\",\"seq\":0,\"error\":null}\n",
+    )
+    .unwrap();
+    let mut engine = Engine::default();
+    match Journal::open(&path, FsyncPolicy::Never, &mut engine) {
+        Err(JournalError::Diverged(0)) => (),
+        res => panic!("unexpected result {:?}", res.err()),
+    }
+}
+
+#[test]
+fn fsync_policy() {
+    assert_eq!(Ok(FsyncPolicy::Always), "always".parse());
+    assert_eq!(Ok(FsyncPolicy::Never), "never".parse());
+    assert_eq!(Ok(FsyncPolicy::Every(100)), "every=100".parse());
+    assert!("every=0".parse::<FsyncPolicy>().is_err());
+    assert!("sometimes".parse::<FsyncPolicy>().is_err());
+}
+
+#[test]
+fn signed_amount() {
+    let adjustment = Transaction {
+        negative: true,
+        ..tx(TransactionType::Adjustment, 2, 4, Some(5))
+    };
+    let precision = Precision::new(2).unwrap();
+    let json = serde_json::to_string(&JournaledTransaction::new(&adjustment, precision)).unwrap();
+    assert!(json.contains(r#""amount":"-0.05""#));
+    let journaled: JournaledTransaction = serde_json::from_str(&json).unwrap();
+    assert_eq!(Ok(adjustment), journaled.parse(precision));
+}
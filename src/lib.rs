@@ -0,0 +1,58 @@
+//! A toy transactions processing engine, for embedding in other services.
+//! The `transactions` CLI tool is a thin consumer of this crate.
+//!
+//! Transactions are applied to an `Engine` one at a time, in chronological order.
+//! Each is either applied or rejected with a `TransactionError`, in which case the engine
+//! is left unchanged. The resulting state can be read through `Engine::accounts()` and the
+//! read-only `Client` views from `Engine::client()`:
+//!
+//! ```
+//! use transactions::{Cents, Engine, SignedCents, Transaction, TransactionError, TransactionType};
+//!
+//! let mut engine = Engine::default();
+//! let deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(Cents::new(100)));
+//! engine.handle_tx(deposit).unwrap();
+//! let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Cents::new(150)));
+//! assert!(matches!(
+//!     engine.handle_tx(withdrawal),
+//!     Err(TransactionError::InsufficientBalance { .. })
+//! ));
+//! let client = engine.client(1).unwrap();
+//! assert_eq!(SignedCents::new(100), client.balance("").available());
+//! ```
+//!
+//! The items re-exported at the crate root are the stable surface of the crate.
+//! Error, transaction type and account status enums, as well as `Transaction`,
+//! are `#[non_exhaustive]` so that new transaction types and rules can be added
+//! without a breaking change.
+//! Amounts are `Cents` at the precision of the `Engine` they belong to,
+//! set through its `AssetConfig` and available from `Engine::precision()`.
+//! Modules hidden from the documentation only exist for the `transactions` CLI
+//! and may change at any time.
+
+mod asset;
+mod authorization;
+pub mod cents;
+pub mod client;
+pub mod engine;
+pub mod err;
+#[doc(hidden)]
+pub mod input;
+#[doc(hidden)]
+pub mod journal;
+mod output;
+mod policy;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod snapshot;
+pub mod transaction;
+
+pub use asset::{AssetConfig, AssetId, AssetPrecision};
+pub use cents::{Amount, Cents, Precision, SignedCents};
+pub use client::{AccountStatus, Balance, Client, ClientId};
+pub use engine::{Account, AccountOrdering, Engine};
+pub use err::TransactionError;
+pub use output::{AccountsDisplay, OutputFormat};
+pub use policy::{DefaultPolicy, Policy};
+pub use transaction::{Transaction, TransactionId, TransactionType};
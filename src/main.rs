@@ -1,39 +1,523 @@
 //! The transactions processing engine CLI tool
 
-mod cents;
-mod client;
-mod engine;
-mod err;
-mod transaction;
-
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use structopt::clap;
 use structopt::StructOpt;
 
-use engine::Engine;
-use transaction::Transaction;
+use transactions::cents::Precision;
+use transactions::engine::parallel::ParallelEngine;
+use transactions::err::JournalError;
+use transactions::input::{self, ColumnAlias, ColumnMapping, InputFormat, InvalidRow};
+use transactions::journal::{FsyncPolicy, Journal};
+use transactions::report::{Rejection, RejectionWriter};
+use transactions::snapshot;
+use transactions::{
+    AccountOrdering, AssetConfig, AssetPrecision, DefaultPolicy, Engine, OutputFormat, Transaction,
+};
+
+/// Exit code when processing was aborted by an unparseable row
+const EXIT_PARSE_ABORTED: i32 = 2;
+/// Exit code when processing completed but some rows could not be parsed
+const EXIT_PARSE_ERRORS: i32 = 3;
+/// Exit code when the journal could not be replayed or written
+const EXIT_JOURNAL_ERROR: i32 = 4;
+/// Exit code when the input could not be opened
+const EXIT_INPUT_ERROR: i32 = 5;
+/// Exit code when the `--state` snapshot could not be loaded or saved
+const EXIT_STATE_ERROR: i32 = 6;
+/// Exit code when the output or the rejects report could not be written
+const EXIT_OUTPUT_ERROR: i32 = 7;
+
+/// What to do with rows that cannot be parsed into a transaction
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ParseErrorPolicy {
+    /// Stop processing at the first bad row
+    Abort,
+    /// Ignore bad rows
+    Skip,
+    /// Ignore bad rows, logging each to stderr and the rejects report if any
+    Report,
+}
+
+impl FromStr for ParseErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            "report" => Ok(Self::Report),
+            _ => Err(format!("Unknown parse error policy: {}", s)),
+        }
+    }
+}
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "transactions", about = "A toy transactions processing engine")]
 struct Opt {
     #[structopt(
         parse(from_os_str),
-        help = "Path to the input csv that contains all transactions in chronological order"
+        help = "Path to the input that contains all transactions in chronological order. \
+                Reads from stdin if omitted or -"
+    )]
+    input: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        possible_values = &["csv", "jsonl", "ndjson"],
+        help = "Format of the input: csv, or jsonl/ndjson for one JSON object per line. \
+                Inferred from the input file extension if omitted, defaulting to csv"
+    )]
+    input_format: Option<InputFormat>,
+
+    #[structopt(
+        long = "column-alias",
+        number_of_values = 1,
+        help = "Another name for an input column, e.g. client_id=client. Can be repeated"
+    )]
+    column_aliases: Vec<ColumnAlias>,
+
+    #[structopt(
+        long,
+        help = "Ignore input columns that are neither a transaction column nor an alias, \
+                rather than refusing the input"
+    )]
+    ignore_unknown_columns: bool,
+
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "Path to write the final state of all accounts to. Writes to stdout if omitted or -"
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Path to write a csv report of all transactions rejected by the engine"
+    )]
+    rejects: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "abort",
+        possible_values = &["abort", "skip", "report"],
+        help = "What to do with rows that cannot be parsed into a transaction"
+    )]
+    on_parse_error: ParseErrorPolicy,
+
+    #[structopt(
+        long,
+        default_value = "csv",
+        possible_values = &["csv", "json", "jsonl", "ndjson"],
+        help = "Format of the account output: csv, a json array, or jsonl/ndjson \
+                for one JSON object per line"
+    )]
+    output_format: OutputFormat,
+
+    #[structopt(
+        long,
+        default_value = "client",
+        possible_values = &["client", "total", "available"],
+        help = "Order in which accounts are output"
+    )]
+    sort: AccountOrdering,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Path to a snapshot of the engine state. \
+                Loaded before processing if it exists, and saved after processing"
+    )]
+    state: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Path to a write-ahead journal of applied transactions. \
+                If it exists, its transactions are replayed and already processed input rows \
+                are skipped. Removed once processing completes"
+    )]
+    journal: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "always",
+        help = "When to fsync the journal: always, never or every=<n> transactions"
+    )]
+    journal_fsync: FsyncPolicy,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of threads to process transactions with. \
+                Transactions are partitioned across threads by client ID. \
+                More than 1 requires --per-client-tx-ids"
+    )]
+    threads: usize,
+
+    #[structopt(
+        long = "asset-precision",
+        number_of_values = 1,
+        help = "Number of decimal places of an asset, e.g. USD=2. Can be repeated. \
+                Assets default to 4 decimal places"
+    )]
+    asset_precisions: Vec<AssetPrecision>,
+
+    #[structopt(
+        long,
+        default_value = "4",
+        help = "Number of decimal places of all amounts, up to 18"
     )]
-    input: PathBuf,
+    precision: Precision,
+
+    #[structopt(
+        long,
+        help = "Maximum number of times a transaction may be disputed, \
+                counting disputes that were resolved. Unlimited if omitted"
+    )]
+    max_disputes: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Let deposit disputes drive available funds negative, \
+                so that the full disputed amount is always held"
+    )]
+    allow_negative: bool,
+
+    #[structopt(
+        long,
+        help = "Number of further transactions of a client within which an authorization \
+                must be captured or voided before its funds are released. Never if omitted"
+    )]
+    auth_expiry: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Seconds after a timestamped transaction within which it may be disputed. \
+                Unlimited if omitted"
+    )]
+    dispute_window: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Seconds after which open disputes are resolved, as of the latest timestamp \
+                of the client's applied transactions. Never if omitted"
+    )]
+    auto_resolve: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Only require transaction IDs to be unique per client, rather than across all clients"
+    )]
+    per_client_tx_ids: bool,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    applied: u64,
+    rejected: u64,
+    parse_errors: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} transactions applied, {} rejected, {} rows could not be parsed",
+            self.applied, self.rejected, self.parse_errors
+        )
+    }
+}
+
+/// Applies transactions to either a single engine or one sharded across threads
+enum Processor {
+    Sequential {
+        engine: Engine,
+        journal: Option<Journal>,
+    },
+    Parallel(ParallelEngine),
+}
+
+impl Processor {
+    fn handle_tx(
+        &mut self,
+        line: u64,
+        tx: Transaction,
+        summary: &mut Summary,
+        rejects: &mut Option<RejectionWriter<File>>,
+    ) -> Result<(), JournalError> {
+        let (engine, journal) = match self {
+            Self::Sequential { engine, journal } => (engine, journal),
+            Self::Parallel(engine) => {
+                engine.handle_tx(line, tx);
+                return Ok(());
+            }
+        };
+        let summary_tx = tx.clone();
+        let seq = journal
+            .as_mut()
+            .map(|j| j.record_tx(line, &tx))
+            .transpose()?;
+        let outcome = engine.handle_tx(tx);
+        if let (Some(j), Some(seq)) = (journal.as_mut(), seq) {
+            j.record_outcome(seq, &outcome)?;
+        }
+        match outcome {
+            Ok(()) => summary.applied += 1,
+            Err(err) => {
+                summary.rejected += 1;
+                write_rejection(rejects, &Rejection::new(line, &summary_tx, &err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejections from parallel processing are only known, and reported, at the end
+    fn finish(
+        self,
+        summary: &mut Summary,
+        rejects: &mut Option<RejectionWriter<File>>,
+    ) -> (Engine, Option<Journal>) {
+        let engine = match self {
+            Self::Sequential { engine, journal } => return (engine, journal),
+            Self::Parallel(engine) => engine,
+        };
+        let result = engine.finish();
+        summary.applied += result.applied;
+        summary.rejected += result.rejected.len() as u64;
+        for rejected in result.rejected.iter() {
+            let rejection = Rejection::new(rejected.line, &rejected.tx, &rejected.err);
+            write_rejection(rejects, &rejection);
+        }
+        (result.engine, None)
+    }
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(opt.input)
-        .unwrap();
-    let mut engine = Engine::new();
-    for result in rdr.records() {
-        let record = result.unwrap();
-        let tx: Transaction = record.deserialize(None).unwrap();
-        // TODO: error handling and logging
-        let _ = engine.handle_tx(tx);
-    }
-    print!("{}", engine);
+    if opt.threads == 0 {
+        clap::Error::with_description(
+            "--threads must be at least 1",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.threads > 1 && opt.journal.is_some() {
+        clap::Error::with_description(
+            "--journal can only be used with --threads 1",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if opt.threads > 1 && !opt.per_client_tx_ids {
+        clap::Error::with_description(
+            "--threads greater than 1 requires --per-client-tx-ids",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    // unwrap safety: nothing has used the precision yet
+    let assets =
+        AssetConfig::new(opt.precision, opt.asset_precisions.clone()).unwrap_or_else(|msg| {
+            clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit()
+        });
+    let input_format = opt
+        .input_format
+        .or_else(|| opt.input.as_ref().and_then(InputFormat::from_path))
+        .unwrap_or_default();
+    let columns = ColumnMapping::new(opt.column_aliases.clone(), opt.ignore_unknown_columns)
+        .unwrap_or_else(|msg| {
+            clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit()
+        });
+    let input = open_input(opt.input.as_ref()).unwrap_or_else(|err| {
+        let msg = format!(
+            "Failed to open input {}: {}",
+            display_path(opt.input.as_ref()),
+            err
+        );
+        fail(EXIT_INPUT_ERROR, msg)
+    });
+    let rows = input::rows(input_format, columns, opt.precision, input);
+    let mut rejects = opt.rejects.as_ref().map(|path| {
+        let file = File::create(path).unwrap_or_else(|err| {
+            let msg = format!(
+                "Failed to create rejects report {}: {}",
+                path.display(),
+                err
+            );
+            fail(EXIT_OUTPUT_ERROR, msg)
+        });
+        RejectionWriter::new(file)
+    });
+    let policy = DefaultPolicy {
+        max_disputes: opt.max_disputes,
+        allow_negative_balance: opt.allow_negative,
+        authorization_expiry: opt.auth_expiry,
+        dispute_window: opt.dispute_window,
+        dispute_auto_resolve: opt.auto_resolve,
+        per_client_tx_ids: opt.per_client_tx_ids,
+    };
+    let mut engine = match opt.state.as_ref() {
+        Some(path) => {
+            let mut engine = snapshot::load(path, opt.precision).unwrap_or_else(|err| {
+                let msg = format!("Failed to load state {}: {}", path.display(), err);
+                fail(EXIT_STATE_ERROR, msg)
+            });
+            engine.set_policy(policy);
+            engine
+        }
+        None => Engine::new(policy),
+    };
+    engine.set_assets(assets);
+    let mut journal = None;
+    let mut resume_after = None;
+    let mut replayed_rejections = Vec::new();
+    if let Some(path) = opt.journal.as_ref() {
+        let (opened, replay) = Journal::open(path, opt.journal_fsync, &mut engine)
+            .unwrap_or_else(|err| fail(EXIT_JOURNAL_ERROR, err));
+        if replay.transactions > 0 {
+            eprintln!("Replayed {} transactions from journal", replay.transactions);
+        }
+        journal = Some(opened);
+        resume_after = replay.last_line;
+        replayed_rejections = replay.rejected;
+    }
+    let mut processor = if opt.threads > 1 {
+        Processor::Parallel(ParallelEngine::new(engine, opt.threads))
+    } else {
+        Processor::Sequential { engine, journal }
+    };
+    let mut summary = Summary::default();
+    let mut replayed_rejections = replayed_rejections.into_iter().peekable();
+    for parsed in rows {
+        let line = match &parsed {
+            Ok((line, _)) | Err(InvalidRow { line, .. }) => *line,
+        };
+        // Rows already applied from the journal are only counted and reported again.
+        // Rows that could not be parsed were never journaled, so they go through as usual
+        if parsed.is_ok() && resume_after.is_some_and(|last_line| line <= last_line) {
+            match replayed_rejections.next_if(|rejected| rejected.line == line) {
+                Some(rejected) => {
+                    summary.rejected += 1;
+                    let rejection = Rejection::new(line, &rejected.tx, &rejected.err);
+                    write_rejection(&mut rejects, &rejection);
+                }
+                None => summary.applied += 1,
+            }
+            continue;
+        }
+        let (line, tx) = match parsed {
+            Ok(parsed) => parsed,
+            Err(InvalidRow { line, record, err }) => {
+                summary.parse_errors += 1;
+                if opt.on_parse_error == ParseErrorPolicy::Abort || !err.is_recoverable() {
+                    eprintln!("Line {}: {}", line, err);
+                    flush_rejects(&mut rejects);
+                    eprintln!("Aborted. {}", summary);
+                    process::exit(EXIT_PARSE_ABORTED);
+                }
+                if opt.on_parse_error == ParseErrorPolicy::Report {
+                    eprintln!("Line {}: {}", line, err);
+                    let rejection = Rejection::from_parse_error(line, record.as_ref(), &err);
+                    write_rejection(&mut rejects, &rejection);
+                }
+                continue;
+            }
+        };
+        if let Err(err) = processor.handle_tx(line, tx, &mut summary, &mut rejects) {
+            fail(EXIT_JOURNAL_ERROR, err);
+        }
+    }
+    let (engine, journal) = processor.finish(&mut summary, &mut rejects);
+    flush_rejects(&mut rejects);
+    let mut out = open_output(opt.output.as_ref()).unwrap_or_else(|err| {
+        let msg = format!(
+            "Failed to create output {}: {}",
+            display_path(opt.output.as_ref()),
+            err
+        );
+        fail(EXIT_OUTPUT_ERROR, msg)
+    });
+    write!(out, "{}", engine.display(opt.sort, opt.output_format))
+        .and_then(|()| out.flush())
+        .unwrap_or_else(|err| {
+            fail(
+                EXIT_OUTPUT_ERROR,
+                format!("Failed to write output: {}", err),
+            )
+        });
+    // the journal goes before the new state is saved: a crash in between loses the run,
+    // which the same input redoes from the previous state, whereas the other way around
+    // the journal would be replayed onto a state that already has its transactions
+    if let Some(j) = journal {
+        j.complete()
+            .unwrap_or_else(|err| fail(EXIT_JOURNAL_ERROR, err));
+    }
+    if let Some(path) = opt.state.as_ref() {
+        snapshot::save(&engine, path).unwrap_or_else(|err| {
+            let msg = format!("Failed to save state {}: {}", path.display(), err);
+            fail(EXIT_STATE_ERROR, msg)
+        });
+    }
+    if summary.parse_errors > 0 {
+        eprintln!("{}", summary);
+        process::exit(EXIT_PARSE_ERRORS);
+    }
+}
+
+/// Reports an error that processing cannot continue after and exits with `code`
+fn fail(code: i32, err: impl fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(code);
+}
+
+/// Writes a rejection to the rejects report, if there is one
+fn write_rejection(rejects: &mut Option<RejectionWriter<File>>, rejection: &Rejection) {
+    if let Some(wtr) = rejects.as_mut() {
+        if let Err(err) = wtr.write(rejection) {
+            fail(
+                EXIT_OUTPUT_ERROR,
+                format!("Failed to write rejects report: {}", err),
+            );
+        }
+    }
+}
+
+fn flush_rejects(rejects: &mut Option<RejectionWriter<File>>) {
+    if let Some(wtr) = rejects.as_mut() {
+        if let Err(err) = wtr.flush() {
+            fail(
+                EXIT_OUTPUT_ERROR,
+                format!("Failed to write rejects report: {}", err),
+            );
+        }
+    }
+}
+
+/// The path of the input or output for messages, `-` for stdin or stdout
+fn display_path(path: Option<&PathBuf>) -> std::path::Display<'_> {
+    path.map_or(Path::new("-"), PathBuf::as_path).display()
+}
+
+/// `-` or no path at all means stdin
+fn open_input(path: Option<&PathBuf>) -> io::Result<Box<dyn io::Read>> {
+    match path {
+        Some(path) if path.as_os_str() != "-" => Ok(Box::new(File::open(path)?)),
+        _ => Ok(Box::new(io::stdin())),
+    }
+}
+
+/// `-` or no path at all means stdout
+fn open_output(path: Option<&PathBuf>) -> io::Result<Box<dyn Write>> {
+    match path {
+        Some(path) if path.as_os_str() != "-" => Ok(Box::new(BufWriter::new(File::create(path)?))),
+        _ => Ok(Box::new(BufWriter::new(io::stdout()))),
+    }
 }
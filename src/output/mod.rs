@@ -0,0 +1,145 @@
+//! Output of the final state of all accounts, in the supported output formats
+
+#[cfg(test)]
+mod test;
+
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cents::Precision;
+use crate::client::{AccountStatus, ClientId};
+use crate::engine::{Account, AccountOrdering, Engine};
+
+/// Format of the account output
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum OutputFormat {
+    /// A header row followed by one row per account
+    #[default]
+    Csv,
+    /// A single JSON array of accounts
+    Json,
+    /// One JSON object per line per account
+    JsonLines,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// An account as output in the JSON formats. Amounts are strings,
+/// so that they are exact at the asset's precision
+#[derive(Serialize, Debug, PartialEq)]
+struct AccountRecord<'a> {
+    client: ClientId,
+    /// Omitted for the default asset
+    #[serde(skip_serializing_if = "str::is_empty")]
+    asset: &'a str,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    status: AccountStatus,
+    open_disputes: usize,
+    charged_back: usize,
+}
+
+impl<'a> AccountRecord<'a> {
+    fn new(account: &Account<'a>, precision: Precision, decimal_places: usize) -> Self {
+        let display = |value| precision.display_wide(value, decimal_places).to_string();
+        Self {
+            client: account.client,
+            asset: account.asset,
+            available: display(account.balance.available().value()),
+            held: display(account.balance.held().into()),
+            total: display(account.balance.total()),
+            locked: account.locked(),
+            status: account.status,
+            open_disputes: account.open_disputes,
+            charged_back: account.charged_back,
+        }
+    }
+}
+
+pub struct AccountsDisplay<'a> {
+    engine: &'a Engine,
+    ordering: AccountOrdering,
+    format: OutputFormat,
+}
+
+impl<'a> AccountsDisplay<'a> {
+    pub fn new(engine: &'a Engine, ordering: AccountOrdering, format: OutputFormat) -> Self {
+        Self {
+            engine,
+            ordering,
+            format,
+        }
+    }
+
+    /// The asset column is only output if any client has transacted in an asset
+    /// other than the default one
+    fn fmt_csv(&self, f: &mut fmt::Formatter, accounts: Vec<Account>) -> fmt::Result {
+        let multi_asset = accounts.iter().any(|a| !a.asset.is_empty());
+        if multi_asset {
+            write!(f, "client,asset,available,held,total,locked,status")?;
+        } else {
+            write!(f, "client,available,held,total,locked,status")?;
+        }
+        for account in accounts {
+            write!(f, "\n{},", account.client)?;
+            if multi_asset {
+                write!(f, "{},", account.asset)?;
+            }
+            let decimal_places = self.engine.assets().decimal_places(account.asset);
+            let precision = self.engine.precision();
+            write!(
+                f,
+                "{},{},{},{},{}",
+                precision.display_wide(account.balance.available().value(), decimal_places),
+                precision.display_places(account.balance.held(), decimal_places),
+                precision.display_wide(account.balance.total(), decimal_places),
+                account.locked(),
+                account.status
+            )?;
+        }
+        Ok(())
+    }
+
+    /// One account per line, either as elements of a JSON array or as JSON Lines
+    fn fmt_json(&self, f: &mut fmt::Formatter, accounts: Vec<Account>) -> fmt::Result {
+        let mut records = accounts.iter().map(|account| {
+            let decimal_places = self.engine.assets().decimal_places(account.asset);
+            // unwrap safety: serializing a struct of strings and integers cannot fail
+            let record = AccountRecord::new(account, self.engine.precision(), decimal_places);
+            serde_json::to_string(&record).unwrap()
+        });
+        if self.format == OutputFormat::JsonLines {
+            return records.try_for_each(|json| writeln!(f, "{}", json));
+        }
+        let records: Vec<String> = records.collect();
+        if records.is_empty() {
+            write!(f, "[]")
+        } else {
+            write!(f, "[\n{}\n]", records.join(",\n"))
+        }
+    }
+}
+
+impl fmt::Display for AccountsDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let accounts = self.engine.accounts(self.ordering);
+        match self.format {
+            OutputFormat::Csv => self.fmt_csv(f, accounts),
+            OutputFormat::Json | OutputFormat::JsonLines => self.fmt_json(f, accounts),
+        }
+    }
+}
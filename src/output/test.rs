@@ -0,0 +1,94 @@
+use crate::asset::{AssetConfig, AssetPrecision};
+use crate::cents::Cents;
+use crate::transaction::{Transaction, TransactionType};
+
+use super::*;
+
+/// Client 1 with one open and one charged back dispute, client 2 with a balance in USD
+fn engine() -> Engine {
+    let mut engine = Engine::default();
+    engine.set_assets(
+        AssetConfig::new(
+            Precision::default(),
+            vec![AssetPrecision {
+                asset: "USD".to_owned(),
+                decimal_places: 2,
+            }],
+        )
+        .unwrap(),
+    );
+    let mut usd = Transaction::new(TransactionType::Deposit, 2, 3, Some(Cents::new(50000)));
+    usd.asset = "USD".to_owned();
+    for tx in [
+        Transaction::new(TransactionType::Deposit, 1, 1, Some(Cents::new(10000))),
+        Transaction::new(TransactionType::Deposit, 1, 2, Some(Cents::new(20000))),
+        usd,
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+        Transaction::new(TransactionType::Dispute, 1, 2, None),
+        Transaction::new(TransactionType::Chargeback, 1, 2, None),
+    ] {
+        engine.handle_tx(tx).unwrap();
+    }
+    engine
+}
+
+#[test]
+fn format() {
+    assert_eq!(Ok(OutputFormat::Csv), "csv".parse());
+    assert_eq!(Ok(OutputFormat::Json), "json".parse());
+    assert_eq!(Ok(OutputFormat::JsonLines), "jsonl".parse());
+    assert_eq!(Ok(OutputFormat::JsonLines), "ndjson".parse());
+    assert!("xml".parse::<OutputFormat>().is_err());
+}
+
+#[test]
+fn csv() {
+    assert_eq!(
+        "client,asset,available,held,total,locked,status\n\
+         1,,0.0000,1.0000,1.0000,true,locked\n\
+         2,USD,5.00,0.00,5.00,false,active",
+        engine()
+            .display(AccountOrdering::ClientId, OutputFormat::Csv)
+            .to_string()
+    );
+}
+
+#[test]
+fn json() {
+    assert_eq!(
+        "[\n\
+         {\"client\":1,\"available\":\"0.0000\",\"held\":\"1.0000\",\"total\":\"1.0000\",\
+         \"locked\":true,\"status\":\"locked\",\"open_disputes\":1,\"charged_back\":1},\n\
+         {\"client\":2,\"asset\":\"USD\",\"available\":\"5.00\",\"held\":\"0.00\",\"total\":\"5.00\",\
+         \"locked\":false,\"status\":\"active\",\"open_disputes\":0,\"charged_back\":0}\n\
+         ]",
+        engine()
+            .display(AccountOrdering::ClientId, OutputFormat::Json)
+            .to_string()
+    );
+    assert_eq!(
+        "[]",
+        Engine::default()
+            .display(AccountOrdering::ClientId, OutputFormat::Json)
+            .to_string()
+    );
+}
+
+#[test]
+fn json_lines() {
+    assert_eq!(
+        "{\"client\":1,\"available\":\"0.0000\",\"held\":\"1.0000\",\"total\":\"1.0000\",\
+         \"locked\":true,\"status\":\"locked\",\"open_disputes\":1,\"charged_back\":1}\n\
+         {\"client\":2,\"asset\":\"USD\",\"available\":\"5.00\",\"held\":\"0.00\",\"total\":\"5.00\",\
+         \"locked\":false,\"status\":\"active\",\"open_disputes\":0,\"charged_back\":0}\n",
+        engine()
+            .display(AccountOrdering::ClientId, OutputFormat::JsonLines)
+            .to_string()
+    );
+    assert_eq!(
+        "",
+        Engine::default()
+            .display(AccountOrdering::ClientId, OutputFormat::JsonLines)
+            .to_string()
+    );
+}
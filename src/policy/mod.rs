@@ -0,0 +1,112 @@
+//! Rules the engine applies to disputes and frozen accounts, so that the same ledger can be
+//! processed under different rulebooks. `DefaultPolicy` is the engine's standard behaviour
+
+#[cfg(test)]
+mod test;
+
+use std::fmt;
+
+/// Every rule defaults to the standard behaviour, so implementations only override what differs
+pub trait Policy: fmt::Debug + Send + Sync {
+    /// Whether a chargeback freezes the account
+    fn chargeback_freezes(&self) -> bool {
+        true
+    }
+
+    /// Whether frozen accounts may still deposit. Frozen accounts can never withdraw
+    fn frozen_may_deposit(&self) -> bool {
+        true
+    }
+
+    /// Whether disputing a deposit fails if there are not enough available funds to hold
+    /// the full amount. Otherwise, only the funds still available are held
+    fn deposit_dispute_requires_available(&self) -> bool {
+        true
+    }
+
+    /// Whether disputing a deposit may drive the available funds negative, so that the full
+    /// disputed amount is always held and the output shows the client's debt.
+    /// Takes precedence over `deposit_dispute_requires_available()`
+    fn allow_negative_balance(&self) -> bool {
+        false
+    }
+
+    /// Whether withdrawals can be disputed, or only deposits
+    fn withdrawals_disputable(&self) -> bool {
+        true
+    }
+
+    /// Whether fees can be disputed like withdrawals
+    fn fees_disputable(&self) -> bool {
+        false
+    }
+
+    /// Number of further transactions of the client within which an authorization must be
+    /// captured or voided. Once expired, the rest of its funds are released. Never if `None`
+    fn authorization_expiry(&self) -> Option<u64> {
+        None
+    }
+
+    /// Seconds after a transaction within which it may be disputed. Transactions and
+    /// disputes without a timestamp can always be disputed. Unlimited if `None`
+    fn dispute_window(&self) -> Option<u64> {
+        None
+    }
+
+    /// Seconds after which an open dispute is resolved, checked after each of the client's
+    /// transactions is applied as of its latest timestamp. Never if `None`
+    fn dispute_auto_resolve(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether transaction IDs only need to be unique per client.
+    /// By default, an ID used by one client is rejected for every other client
+    fn per_client_tx_ids(&self) -> bool {
+        false
+    }
+
+    /// Maximum number of times a transaction may be disputed, counting disputes that were
+    /// later resolved. `Some(1)` means resolved transactions cannot be re-disputed.
+    /// Unlimited if `None`
+    fn max_disputes(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The standard rules, with optional limits on disputes and authorizations
+/// and optional negative balances
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DefaultPolicy {
+    pub max_disputes: Option<usize>,
+    pub allow_negative_balance: bool,
+    pub authorization_expiry: Option<u64>,
+    pub dispute_window: Option<u64>,
+    pub dispute_auto_resolve: Option<u64>,
+    pub per_client_tx_ids: bool,
+}
+
+impl Policy for DefaultPolicy {
+    fn allow_negative_balance(&self) -> bool {
+        self.allow_negative_balance
+    }
+
+    fn authorization_expiry(&self) -> Option<u64> {
+        self.authorization_expiry
+    }
+
+    fn dispute_window(&self) -> Option<u64> {
+        self.dispute_window
+    }
+
+    fn dispute_auto_resolve(&self) -> Option<u64> {
+        self.dispute_auto_resolve
+    }
+
+    fn per_client_tx_ids(&self) -> bool {
+        self.per_client_tx_ids
+    }
+
+    fn max_disputes(&self) -> Option<usize> {
+        self.max_disputes
+    }
+}
@@ -0,0 +1,161 @@
+use crate::cents::Precision;
+use crate::engine::Engine;
+use crate::err::TransactionError;
+use crate::transaction::{tx, TransactionType};
+
+use super::*;
+
+/// A rulebook that differs from the default in every rule
+#[derive(Debug)]
+struct PartnerPolicy;
+
+impl Policy for PartnerPolicy {
+    fn chargeback_freezes(&self) -> bool {
+        false
+    }
+
+    fn frozen_may_deposit(&self) -> bool {
+        false
+    }
+
+    fn deposit_dispute_requires_available(&self) -> bool {
+        false
+    }
+
+    fn withdrawals_disputable(&self) -> bool {
+        false
+    }
+
+    fn fees_disputable(&self) -> bool {
+        true
+    }
+
+    fn max_disputes(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+/// Deposits 10, withdraws 4, then disputes the deposit
+fn run(engine: &mut Engine) -> Vec<Result<(), TransactionError>> {
+    [
+        tx(TransactionType::Deposit, 1, 1, Some(10)),
+        tx(TransactionType::Withdrawal, 1, 2, Some(4)),
+        tx(TransactionType::Dispute, 1, 2, None),
+        tx(TransactionType::Dispute, 1, 1, None),
+        tx(TransactionType::Chargeback, 1, 1, None),
+        tx(TransactionType::Deposit, 1, 3, Some(1)),
+    ]
+    .iter()
+    .map(|tx| engine.handle_tx(tx.clone()))
+    .collect()
+}
+
+#[test]
+fn default_policy() {
+    let mut engine = Engine::new(DefaultPolicy::default());
+    assert_eq!(
+        vec![
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Err(TransactionError::InsufficientBalance {
+                available: Precision::default().amount(6),
+                requested: Precision::default().amount(10)
+            }),
+            Err(TransactionError::TransactionNotUnderDispute(1)),
+            Ok(()),
+        ],
+        run(&mut engine)
+    );
+    assert_eq!(
+        "client,available,held,total,locked,status\n1,0.0007,0.0004,0.0011,false,active",
+        engine.to_string()
+    );
+}
+
+#[test]
+fn partner_policy() {
+    let mut engine = Engine::new(PartnerPolicy);
+    assert_eq!(
+        vec![
+            Ok(()),
+            Ok(()),
+            Err(TransactionError::TransactionIndisputable(
+                TransactionType::Withdrawal
+            )),
+            // only the 6 still available are held and charged back
+            Ok(()),
+            Ok(()),
+            Ok(()),
+        ],
+        run(&mut engine)
+    );
+    assert_eq!(
+        "client,available,held,total,locked,status\n1,0.0001,0.0000,0.0001,false,active",
+        engine.to_string()
+    );
+    assert_eq!(
+        Err(TransactionError::DisputeLimitReached {
+            tx: 3,
+            max_disputes: 1
+        }),
+        [
+            tx(TransactionType::Dispute, 1, 3, None),
+            tx(TransactionType::Resolve, 1, 3, None),
+            tx(TransactionType::Dispute, 1, 3, None),
+        ]
+        .iter()
+        .map(|tx| engine.handle_tx(tx.clone()))
+        .collect::<Result<Vec<()>, _>>()
+    );
+}
+
+#[test]
+fn frozen_deposit() {
+    let mut frozen = Engine::default();
+    for tx in [
+        tx(TransactionType::Deposit, 1, 1, Some(10)),
+        tx(TransactionType::Dispute, 1, 1, None),
+        tx(TransactionType::Chargeback, 1, 1, None),
+    ] {
+        frozen.handle_tx(tx).unwrap();
+    }
+    assert_eq!(
+        Ok(()),
+        frozen.handle_tx(tx(TransactionType::Deposit, 1, 2, Some(1)))
+    );
+    frozen.set_policy(PartnerPolicy);
+    assert_eq!(
+        Err(TransactionError::AccountFrozen),
+        frozen.handle_tx(tx(TransactionType::Deposit, 1, 3, Some(1)))
+    );
+}
+
+#[test]
+fn disputable_fees() {
+    let txs = [
+        tx(TransactionType::Deposit, 1, 1, Some(10)),
+        tx(TransactionType::Fee, 1, 2, Some(3)),
+        tx(TransactionType::Dispute, 1, 2, None),
+        tx(TransactionType::Chargeback, 1, 2, None),
+    ];
+    let mut engine = Engine::default();
+    assert_eq!(
+        Err(TransactionError::TransactionIndisputable(
+            TransactionType::Fee
+        )),
+        txs.iter()
+            .map(|tx| engine.handle_tx(tx.clone()))
+            .collect::<Result<Vec<()>, _>>()
+    );
+    // a charged back fee is refunded, like a withdrawal
+    let mut engine = Engine::new(PartnerPolicy);
+    for tx in txs {
+        engine.handle_tx(tx).unwrap();
+    }
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0010,0.0000,0.0010,false,active",
+        engine.to_string()
+    );
+}
@@ -0,0 +1,82 @@
+//! Reporting of rows that the engine rejected, so they can be reconciled later
+
+#[cfg(test)]
+mod test;
+
+use serde::Serialize;
+use std::io;
+
+use crate::client::ClientId;
+use crate::err::{ParseError, TransactionError};
+use crate::transaction::{Transaction, TransactionId, TransactionType};
+
+/// A transaction rejected by the engine, along with its input line number
+#[derive(Debug, PartialEq)]
+pub struct Rejected {
+    pub line: u64,
+    pub tx: Transaction,
+    pub err: TransactionError,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Rejection {
+    pub line: u64,
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new(line: u64, tx: &Transaction, err: &TransactionError) -> Self {
+        Self {
+            line,
+            client: Some(tx.client),
+            tx: Some(tx.tx),
+            transaction_type: Some(tx.transaction_type),
+            error: err.kind(),
+            message: err.to_string(),
+        }
+    }
+
+    /// A row that could not be parsed into a transaction.
+    /// Whatever fields can still be made out of the raw row are filled in
+    pub fn from_parse_error(
+        line: u64,
+        record: Option<&csv::StringRecord>,
+        err: &ParseError,
+    ) -> Self {
+        let field = |i| record.and_then(|r| r.get(i));
+        Self {
+            line,
+            client: field(1).and_then(|s| s.parse().ok()),
+            tx: field(2).and_then(|s| s.parse().ok()),
+            transaction_type: None,
+            error: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Writes one csv row per rejected transaction
+pub struct RejectionWriter<W: io::Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: io::Write> RejectionWriter<W> {
+    pub fn new(wtr: W) -> Self {
+        Self {
+            wtr: csv::Writer::from_writer(wtr),
+        }
+    }
+
+    pub fn write(&mut self, rejection: &Rejection) -> csv::Result<()> {
+        self.wtr.serialize(rejection)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
@@ -0,0 +1,74 @@
+use crate::cents::Precision;
+use crate::err::{ParseError, TransactionError};
+use crate::transaction::{tx, TransactionType};
+
+use super::*;
+
+fn write_rejections(rejections: Vec<Rejection>) -> String {
+    let mut buf = Vec::new();
+    {
+        let mut wtr = RejectionWriter::new(&mut buf);
+        for rejection in rejections.iter() {
+            wtr.write(rejection).unwrap();
+        }
+        wtr.flush().unwrap();
+    }
+    String::from_utf8(buf).unwrap()
+}
+
+#[test]
+fn rejection_from_error() {
+    let tx = tx(TransactionType::Withdrawal, 3, 7, Some(10000));
+    let err = TransactionError::InsufficientBalance {
+        available: Precision::default().amount(1),
+        requested: Precision::default().amount(10000),
+    };
+    let rejection = Rejection::new(5, &tx, &err);
+    assert_eq!(
+        Rejection {
+            line: 5,
+            client: Some(3),
+            tx: Some(7),
+            transaction_type: Some(TransactionType::Withdrawal),
+            error: "InsufficientBalance",
+            message: "Insufficient Balance. Available: 0.0001. Requested withdrawal: 1.0000"
+                .to_owned(),
+        },
+        rejection
+    );
+}
+
+#[test]
+fn rejection_from_parse_error() {
+    let record = csv::StringRecord::from(vec!["deposit", "12", "x", "1.0"]);
+    let err = ParseError::InvalidTransactionId("invalid digit found in string".to_owned());
+    let rejection = Rejection::from_parse_error(4, Some(&record), &err);
+    assert_eq!(
+        Rejection {
+            line: 4,
+            client: Some(12),
+            tx: None,
+            transaction_type: None,
+            error: "InvalidTransactionId",
+            message: "Invalid transaction ID: invalid digit found in string".to_owned(),
+        },
+        rejection
+    );
+}
+
+#[test]
+fn write() {
+    let dispute = tx(TransactionType::Dispute, 1, 2, None);
+    let out = write_rejections(vec![
+        Rejection::new(2, &dispute, &TransactionError::TransactionDoesNotExist(2)),
+        Rejection::new(3, &dispute, &TransactionError::AccountFrozen),
+        Rejection::from_parse_error(4, None, &ParseError::MalformedRow("bad".to_owned())),
+    ]);
+    assert_eq!(
+        "line,client,tx,type,error,message\n\
+         2,1,2,dispute,TransactionDoesNotExist,Transaction 2 does not exist\n\
+         3,1,2,dispute,AccountFrozen,Account frozen\n\
+         4,,,,MalformedRow,Malformed row: bad\n",
+        out
+    );
+}
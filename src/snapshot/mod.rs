@@ -0,0 +1,91 @@
+//! Versioned on-disk snapshots of the complete engine state,
+//! so that transactions in later inputs can refer to transactions in earlier ones
+
+#[cfg(test)]
+mod test;
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::cents::Precision;
+use crate::engine::Engine;
+use crate::err::SnapshotError;
+
+/// Bump this whenever the serialized form of `Engine` changes
+pub const SNAPSHOT_VERSION: u32 = 12;
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+/// Amounts are stored as raw cents, so the snapshot is only valid at the same precision
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    decimal_places: usize,
+    engine: &'a Engine,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    decimal_places: usize,
+    engine: Engine,
+}
+
+pub fn write<W: io::Write>(engine: &Engine, wtr: W) -> Result<(), SnapshotError> {
+    let snapshot = SnapshotRef {
+        version: SNAPSHOT_VERSION,
+        decimal_places: engine.precision().decimal_places(),
+        engine,
+    };
+    serde_json::to_writer(wtr, &snapshot)?;
+    Ok(())
+}
+
+/// Restores an engine from a snapshot written at `precision`. The engine has the default
+/// asset configuration, which should be replaced by one at the same precision
+pub fn read<R: io::Read>(mut rdr: R, precision: Precision) -> Result<Engine, SnapshotError> {
+    let mut bytes = Vec::new();
+    rdr.read_to_end(&mut bytes)?;
+    let header: SnapshotHeader = serde_json::from_slice(&bytes)?;
+    if header.version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion {
+            found: header.version,
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+    let snapshot: Snapshot = serde_json::from_slice(&bytes)?;
+    let decimal_places = precision.decimal_places();
+    if snapshot.decimal_places != decimal_places {
+        return Err(SnapshotError::PrecisionMismatch {
+            found: snapshot.decimal_places,
+            expected: decimal_places,
+        });
+    }
+    Ok(snapshot.engine)
+}
+
+/// Writes to a temporary file first and then renames it,
+/// so that a crash midway never leaves a partially written snapshot at `path`
+pub fn save<P: AsRef<Path>>(engine: &Engine, path: P) -> Result<(), SnapshotError> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+    let mut wtr = BufWriter::new(File::create(&tmp_path)?);
+    write(engine, &mut wtr)?;
+    wtr.flush()?;
+    wtr.get_ref().sync_all()?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Returns a new engine if there is no snapshot at `path` yet, see `read()`
+pub fn load<P: AsRef<Path>>(path: P, precision: Precision) -> Result<Engine, SnapshotError> {
+    match File::open(path) {
+        Ok(file) => read(BufReader::new(file), precision),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Engine::default()),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,89 @@
+use crate::engine::Engine;
+use crate::err::SnapshotError;
+use crate::transaction::{tx, TransactionType};
+
+use super::*;
+
+fn roundtrip(engine: &Engine) -> Engine {
+    let mut buf = Vec::new();
+    write(engine, &mut buf).unwrap();
+    read(buf.as_slice(), Precision::default()).unwrap()
+}
+
+#[test]
+fn restore() {
+    let mut engine = Engine::default();
+    let txs = vec![
+        tx(TransactionType::Deposit, 1, 1, Some(100)),
+        tx(TransactionType::Deposit, 1, 2, Some(50)),
+        tx(TransactionType::Dispute, 1, 2, None),
+        tx(TransactionType::Deposit, 2, 3, Some(20)),
+        tx(TransactionType::Withdrawal, 2, 4, Some(5)),
+        tx(TransactionType::Dispute, 2, 4, None),
+        tx(TransactionType::Chargeback, 2, 4, None),
+    ];
+    for tx in txs {
+        let _ = engine.handle_tx(tx);
+    }
+    let mut restored = roundtrip(&engine);
+    assert_eq!(engine, restored);
+
+    // disputes, resolves and chargebacks still apply to transactions from before the snapshot
+    let txs = vec![
+        tx(TransactionType::Resolve, 1, 2, None),
+        tx(TransactionType::Dispute, 1, 1, None),
+        tx(TransactionType::Deposit, 1, 2, Some(1)),
+        tx(TransactionType::Dispute, 2, 4, None),
+        // transaction IDs stay unique across clients
+        tx(TransactionType::Deposit, 2, 1, Some(1)),
+    ];
+    for tx in txs {
+        let expected = engine.handle_tx(tx.clone());
+        assert_eq!(expected, restored.handle_tx(tx));
+    }
+    assert_eq!(engine, restored);
+    assert_eq!(
+        "client,available,held,total,locked,status\n\
+         1,0.0050,0.0100,0.0150,false,active\n\
+         2,0.0020,0.0000,0.0020,true,locked",
+        format!("{}", restored)
+    );
+}
+
+#[test]
+fn unsupported_version() {
+    let snapshot = format!(
+        "{{\"version\":{},\"decimal_places\":4,\"engine\":{{\"clients\":{{}}}}}}",
+        SNAPSHOT_VERSION + 1
+    );
+    match read(snapshot.as_bytes(), Precision::default()) {
+        Err(SnapshotError::UnsupportedVersion { found, expected }) => {
+            assert_eq!(SNAPSHOT_VERSION + 1, found);
+            assert_eq!(SNAPSHOT_VERSION, expected);
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn malformed() {
+    assert!(matches!(
+        read("{\"version\":1".as_bytes(), Precision::default()),
+        Err(SnapshotError::Format(_))
+    ));
+}
+
+#[test]
+fn precision_mismatch() {
+    let snapshot = format!(
+        "{{\"version\":{},\"decimal_places\":2,\"engine\":{{\"clients\":{{}}}}}}",
+        SNAPSHOT_VERSION
+    );
+    match read(snapshot.as_bytes(), Precision::default()) {
+        Err(SnapshotError::PrecisionMismatch { found, expected }) => {
+            assert_eq!(2, found);
+            assert_eq!(4, expected);
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+}
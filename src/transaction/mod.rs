@@ -1,56 +1,366 @@
-//! Struct representing a transaction of the 5 different transaction types
+//! Struct representing a transaction of the different transaction types
 
 #[cfg(test)]
 mod test;
 
-use serde::{self, Deserialize};
+use serde::{self, Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
 
-use crate::cents::Cents;
+use crate::asset::AssetId;
+use crate::cents::{Cents, Precision, SignedCents};
 use crate::client::ClientId;
-use crate::err::TransactionError;
+use crate::err::{ParseError, TransactionError};
 
 pub type TransactionId = u32;
 
+/// Seconds since the Unix epoch
+pub type Timestamp = u64;
+
+#[derive(Deserialize, Serialize, PartialEq, Debug, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
-#[derive(Deserialize, PartialEq, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves funds from `client` to client `to`
+    Transfer,
+    /// Administrative: freezes the account
+    Lock,
+    /// Administrative: unfreezes a frozen account
+    Unlock,
+    /// Administrative: closes the account for good
+    Close,
+    /// Debits a fee from the available funds
+    Fee,
+    /// Credits or debits the available funds by a signed amount, e.g. to correct an error
+    Adjustment,
+    /// Undoes the effect of the client's earlier transaction `tx` on the available funds
+    Reversal,
+    /// Holds funds until they are captured or voided
+    Authorize,
+    /// Debits all or part of the funds held by authorization `tx`
+    Capture,
+    /// Releases the rest of the funds held by authorization `tx`
+    Void,
+}
+
+impl TransactionType {
+    /// Whether the transaction is recorded under its own transaction ID
+    pub fn creates_record(&self) -> bool {
+        matches!(
+            self,
+            Self::Deposit
+                | Self::Withdrawal
+                | Self::Transfer
+                | Self::Fee
+                | Self::Adjustment
+                | Self::Authorize
+        )
+    }
+
+    /// Whether the transaction refers to an earlier transaction of the client by its ID
+    pub fn references_record(&self) -> bool {
+        matches!(
+            self,
+            Self::Dispute
+                | Self::Resolve
+                | Self::Chargeback
+                | Self::Reversal
+                | Self::Capture
+                | Self::Void
+        )
+    }
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+/// A row of the input. Build one with `new()`, then set any optional columns
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
 pub struct Transaction {
     pub transaction_type: TransactionType,
     pub client: ClientId,
     pub tx: TransactionId,
-    #[serde(deserialize_with = "csv::invalid_option")]
+    /// Magnitude of the amount, see `signed_amount()`
     pub amount: Option<Cents>,
+    /// Optional column, defaults to the default asset
+    pub asset: AssetId,
+    /// Optional column, the destination client of a transfer
+    pub to: Option<ClientId>,
+    /// Whether the amount is negative. Only adjustments can be negative
+    pub negative: bool,
+    /// Optional column, when the transaction happened
+    pub timestamp: Option<Timestamp>,
+}
+
+impl Transaction {
+    /// A transaction in the default asset with a non-negative amount and no optional columns
+    pub fn new(
+        transaction_type: TransactionType,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Cents>,
+    ) -> Self {
+        Self {
+            transaction_type,
+            client,
+            tx,
+            amount,
+            asset: AssetId::new(),
+            to: None,
+            negative: false,
+            timestamp: None,
+        }
+    }
+
+    pub fn signed_amount(&self) -> Option<SignedCents> {
+        self.amount.map(|amount| {
+            if self.negative {
+                SignedCents::debit(amount)
+            } else {
+                SignedCents::credit(amount)
+            }
+        })
+    }
+}
+
+/// A transaction of `amount` raw cents in the default asset, for brevity in tests
+#[cfg(test)]
+pub(crate) fn tx(
+    transaction_type: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<u64>,
+) -> Transaction {
+    Transaction::new(transaction_type, client, tx, amount.map(Cents::new))
+}
+
+/// A row of the input, before its amount is parsed at the engine's precision
+#[derive(Deserialize, Debug)]
+pub(crate) struct TransactionRow {
+    transaction_type: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<String>,
+    #[serde(default)]
+    asset: AssetId,
+    #[serde(default)]
+    to: Option<ClientId>,
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+impl TransactionRow {
+    /// The transaction with its amount parsed at `precision`. Only adjustments can be negative
+    pub(crate) fn parse(self, precision: Precision) -> Result<Transaction, ParseError> {
+        let amount = self
+            .amount
+            .map(|amount| precision.parse_signed(&amount))
+            .transpose()
+            .map_err(|err| ParseError::InvalidAmount(err.to_string()))?;
+        let negative = amount.is_some_and(|amount| amount.is_negative());
+        if negative && self.transaction_type != TransactionType::Adjustment {
+            return Err(ParseError::InvalidAmount(format!(
+                "Negative amount for transaction type {:?}. Only adjustments can be negative",
+                self.transaction_type
+            )));
+        }
+        Ok(Transaction {
+            transaction_type: self.transaction_type,
+            client: self.client,
+            tx: self.tx,
+            amount: amount.map(|amount| amount.magnitude()),
+            asset: self.asset,
+            to: self.to,
+            negative,
+            timestamp: self.timestamp,
+        })
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub enum DisputableTransactionType {
     Deposit,
     Withdrawal,
+    /// The destination's side of a transfer
+    TransferIn {
+        from: ClientId,
+    },
+    /// The source's side of a transfer
+    TransferOut {
+        to: ClientId,
+    },
+    Fee,
+    /// Adjustments are never disputable, only reversible
+    Adjustment {
+        debit: bool,
+    },
 }
 
-#[derive(Debug)]
+/// Where a disputable transaction is in its dispute lifecycle.
+/// A transaction starts out settled and can only be charged back while disputed.
+/// Once charged back or reversed, it can never be disputed again
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum DisputeState {
+    Settled,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Reversed,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct DisputableTransaction {
-    pub transaction_type: DisputableTransactionType,
-    pub amount: Cents,
+    pub(crate) transaction_type: DisputableTransactionType,
+    #[serde(with = "crate::cents::raw")]
+    pub(crate) amount: Cents,
+    pub(crate) asset: AssetId,
+    /// Funds held for the current dispute, zero if not under dispute
+    #[serde(with = "crate::cents::raw")]
+    pub(crate) held: Cents,
+    /// Funds charged back so far, across all partial chargebacks
+    #[serde(with = "crate::cents::raw")]
+    pub(crate) charged_back: Cents,
+    pub(crate) timestamp: Option<Timestamp>,
+    /// When the current dispute started, if under dispute and known
+    pub(crate) disputed_at: Option<Timestamp>,
+    state: DisputeState,
+    /// Every state the transaction has previously been in, oldest first
+    history: Vec<DisputeState>,
+}
+
+impl DisputableTransaction {
+    pub(crate) fn new(
+        transaction_type: DisputableTransactionType,
+        amount: Cents,
+        asset: AssetId,
+        timestamp: Option<Timestamp>,
+    ) -> Self {
+        Self {
+            transaction_type,
+            amount,
+            asset,
+            held: Cents::ZERO,
+            charged_back: Cents::ZERO,
+            timestamp,
+            disputed_at: None,
+            state: DisputeState::Settled,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn transaction_type(&self) -> DisputableTransactionType {
+        self.transaction_type
+    }
+
+    pub fn amount(&self) -> Cents {
+        self.amount
+    }
+
+    pub fn asset(&self) -> &str {
+        &self.asset
+    }
+
+    /// Funds held for the current dispute, zero if not under dispute
+    pub fn held(&self) -> Cents {
+        self.held
+    }
+
+    /// Funds charged back so far, across all partial chargebacks
+    pub fn charged_back(&self) -> Cents {
+        self.charged_back
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    /// When the current dispute started, if under dispute and known
+    pub fn disputed_at(&self) -> Option<Timestamp> {
+        self.disputed_at
+    }
+
+    pub fn state(&self) -> DisputeState {
+        self.state
+    }
+
+    /// Number of times the transaction has been disputed, including a current dispute
+    pub fn dispute_count(&self) -> usize {
+        self.history
+            .iter()
+            .chain(std::iter::once(&self.state))
+            .filter(|state| **state == DisputeState::Disputed)
+            .count()
+    }
+
+    /// Part of the amount that is neither held for a dispute nor charged back
+    pub fn undisputed(&self) -> Cents {
+        self.amount
+            .saturating_sub(self.held)
+            .saturating_sub(self.charged_back)
+    }
+
+    /// Moves the transaction to `state`. Callers are responsible for checking
+    /// that the transition is allowed
+    pub(crate) fn transition(&mut self, state: DisputeState) {
+        self.history.push(self.state);
+        self.state = state;
+    }
+
+    /// Holds `amount` more for a dispute, starting one at `timestamp` if not already
+    /// under dispute. Callers are responsible for checking that at most `undisputed()`
+    /// is disputed
+    pub(crate) fn dispute(&mut self, amount: Cents, timestamp: Option<Timestamp>) {
+        // unwrap safety: held never exceeds the transaction amount
+        self.held = (self.held + amount).unwrap();
+        if self.state != DisputeState::Disputed {
+            self.transition(DisputeState::Disputed);
+            self.disputed_at = timestamp;
+        }
+    }
+
+    /// Whether the transaction has been under dispute for longer than `period` at `now`
+    pub fn dispute_overdue(&self, now: Timestamp, period: u64) -> bool {
+        match self.disputed_at {
+            Some(disputed_at) if self.state == DisputeState::Disputed => {
+                now.saturating_sub(disputed_at) > period
+            }
+            _ => false,
+        }
+    }
+
+    /// Releases `amount` of the held funds, either back to the client or charged back.
+    /// The dispute ends once nothing is held, as charged back if any part of it ever was.
+    /// Callers are responsible for checking that at most `held` is released
+    pub(crate) fn settle(&mut self, amount: Cents, charge_back: bool) {
+        // unwrap safety: callers check that at most held is released
+        self.held = (self.held - amount).unwrap();
+        if charge_back {
+            // unwrap safety: charged_back never exceeds the transaction amount
+            self.charged_back = (self.charged_back + amount).unwrap();
+        }
+        if self.held == Cents::ZERO {
+            self.disputed_at = None;
+            if self.charged_back == Cents::ZERO {
+                self.transition(DisputeState::Resolved);
+            } else {
+                self.transition(DisputeState::ChargedBack);
+            }
+        }
+    }
 }
 
-impl TryFrom<TransactionType> for DisputableTransactionType {
+impl TryFrom<&Transaction> for DisputableTransactionType {
     type Error = TransactionError;
 
-    fn try_from(t: TransactionType) -> Result<Self, Self::Error> {
-        match t {
+    fn try_from(tx: &Transaction) -> Result<Self, Self::Error> {
+        match tx.transaction_type {
             TransactionType::Deposit => Ok(Self::Deposit),
             TransactionType::Withdrawal => Ok(Self::Withdrawal),
-            _ => Err(TransactionError::TransactionIndisputable(t)),
+            TransactionType::Fee => Ok(Self::Fee),
+            TransactionType::Adjustment => Ok(Self::Adjustment { debit: tx.negative }),
+            t => Err(TransactionError::TransactionIndisputable(t)),
         }
     }
 }
@@ -59,10 +369,13 @@ impl TryFrom<Transaction> for DisputableTransaction {
     type Error = TransactionError;
 
     fn try_from(tx: Transaction) -> Result<Self, Self::Error> {
-        let transaction_type: DisputableTransactionType = tx.transaction_type.try_into()?;
-        Ok(DisputableTransaction {
+        let transaction_type: DisputableTransactionType = (&tx).try_into()?;
+        let amount = tx.amount.ok_or(TransactionError::NoAmount)?;
+        Ok(DisputableTransaction::new(
             transaction_type,
-            amount: tx.amount.ok_or_else(|| TransactionError::NoAmount)?,
-        })
+            amount,
+            tx.asset,
+            tx.timestamp,
+        ))
     }
 }
@@ -5,13 +5,8 @@ use crate::err::TransactionError;
 
 use super::*;
 
-fn check_into_disputable(amount: Cents, tt: TransactionType) {
-    let tx = Transaction {
-        transaction_type: tt,
-        client: 0,
-        tx: 0,
-        amount: Some(amount),
-    };
+fn check_into_disputable(amount: u64, tt: TransactionType) {
+    let tx = tx(tt, 0, 0, Some(amount));
     let disputable: DisputableTransaction = tx.try_into().unwrap();
     let expected_type = match tt {
         TransactionType::Withdrawal => DisputableTransactionType::Withdrawal,
@@ -19,22 +14,17 @@ fn check_into_disputable(amount: Cents, tt: TransactionType) {
         _ => panic!("unreachable"),
     };
     assert_eq!(expected_type, disputable.transaction_type);
-    assert_eq!(amount, disputable.amount);
+    assert_eq!(Cents::new(amount), disputable.amount);
 }
 
 #[test]
 fn into_disputable() {
-    check_into_disputable(Cents::new(69), TransactionType::Withdrawal);
-    check_into_disputable(Cents::new(23433242), TransactionType::Deposit);
+    check_into_disputable(69, TransactionType::Withdrawal);
+    check_into_disputable(23433242, TransactionType::Deposit);
 }
 
 fn check_indisputable(tt: TransactionType) {
-    let tx = Transaction {
-        transaction_type: tt,
-        client: 0,
-        tx: 0,
-        amount: Some(Cents::new(1)),
-    };
+    let tx = tx(tt, 0, 0, Some(1));
     let res: Result<DisputableTransaction, TransactionError> = tx.try_into();
     assert_eq!(
         TransactionError::TransactionIndisputable(tt),
@@ -50,12 +40,7 @@ fn indisputable() {
 }
 
 fn check_malformed_disputable(tt: TransactionType) {
-    let tx = Transaction {
-        transaction_type: tt,
-        client: 0,
-        tx: 0,
-        amount: None,
-    };
+    let tx = tx(tt, 0, 0, None);
     let res: Result<DisputableTransaction, TransactionError> = tx.try_into();
     assert_eq!(TransactionError::NoAmount, res.unwrap_err());
 }
@@ -68,55 +53,118 @@ fn malformed_disputable() {
 
 fn check_deser(row: Vec<&str>, expected: Transaction) {
     let record = StringRecord::from(row);
-    let tx: Transaction = record.deserialize(None).unwrap();
-    assert_eq!(expected, tx);
+    let row: TransactionRow = record.deserialize(None).unwrap();
+    assert_eq!(Ok(expected), row.parse(Precision::default()));
 }
 
 #[test]
 fn deser() {
     check_deser(
         vec!["withdrawal", "0", "1", "1.0234"],
-        Transaction {
-            transaction_type: TransactionType::Withdrawal,
-            client: 0,
-            tx: 1,
-            amount: Some(Cents::new(10234)),
-        },
+        tx(TransactionType::Withdrawal, 0, 1, Some(10234)),
     );
     check_deser(
         vec!["deposit", "12314", "3444454514", "0.0210"],
-        Transaction {
-            transaction_type: TransactionType::Deposit,
-            client: 12314,
-            tx: 3444454514,
-            amount: Some(Cents::new(210)),
-        },
+        tx(TransactionType::Deposit, 12314, 3444454514, Some(210)),
     );
     check_deser(
         vec!["dispute", "23414", "459567213", ""],
+        tx(TransactionType::Dispute, 23414, 459567213, None),
+    );
+    check_deser(
+        vec!["resolve", "1243", "2322", ""],
+        tx(TransactionType::Resolve, 1243, 2322, None),
+    );
+    check_deser(
+        vec!["chargeback", "5245", "3453", ""],
+        tx(TransactionType::Chargeback, 5245, 3453, None),
+    );
+}
+
+#[test]
+fn deser_asset() {
+    check_deser(
+        vec!["deposit", "1", "2", "1.5", "USD"],
         Transaction {
-            transaction_type: TransactionType::Dispute,
-            client: 23414,
-            tx: 459567213,
-            amount: None,
+            asset: "USD".to_owned(),
+            ..tx(TransactionType::Deposit, 1, 2, Some(15000))
         },
     );
     check_deser(
-        vec!["resolve", "1243", "2322", ""],
+        vec!["dispute", "1", "2", "", ""],
+        tx(TransactionType::Dispute, 1, 2, None),
+    );
+}
+
+#[test]
+fn dispute_history() {
+    let tx = tx(TransactionType::Deposit, 0, 0, Some(1));
+    let mut disputable: DisputableTransaction = tx.try_into().unwrap();
+    assert_eq!(DisputeState::Settled, disputable.state());
+    assert_eq!(0, disputable.dispute_count());
+    disputable.transition(DisputeState::Disputed);
+    assert_eq!(1, disputable.dispute_count());
+    disputable.transition(DisputeState::Resolved);
+    disputable.transition(DisputeState::Disputed);
+    assert_eq!(DisputeState::Disputed, disputable.state());
+    assert_eq!(2, disputable.dispute_count());
+    assert_eq!(
+        vec![
+            DisputeState::Settled,
+            DisputeState::Disputed,
+            DisputeState::Resolved
+        ],
+        disputable.history
+    );
+}
+
+#[test]
+fn deser_signed() {
+    check_deser(
+        vec!["adjustment", "1", "2", "-0.5"],
         Transaction {
-            transaction_type: TransactionType::Resolve,
-            client: 1243,
-            tx: 2322,
-            amount: None,
+            negative: true,
+            ..tx(TransactionType::Adjustment, 1, 2, Some(5000))
         },
     );
+    let record = StringRecord::from(vec!["fee", "1", "3", "-0.5"]);
+    let row: TransactionRow = record.deserialize(None).unwrap();
+    assert_eq!(
+        "InvalidAmount",
+        row.parse(Precision::default()).unwrap_err().kind()
+    );
+}
+
+#[test]
+fn deser_timestamp() {
     check_deser(
-        vec!["chargeback", "5245", "3453", ""],
+        vec!["deposit", "1", "2", "1.0", "", "", "1700000000"],
         Transaction {
-            transaction_type: TransactionType::Chargeback,
-            client: 5245,
-            tx: 3453,
-            amount: None,
+            timestamp: Some(1700000000),
+            ..tx(TransactionType::Deposit, 1, 2, Some(10000))
         },
     );
+    let record = StringRecord::from(vec!["deposit", "1", "2", "1.0", "", "", "yesterday"]);
+    let err = record.deserialize::<TransactionRow>(None).unwrap_err();
+    assert_eq!(
+        "InvalidTimestamp",
+        crate::err::ParseError::from_record(&record, err).kind()
+    );
+}
+
+#[test]
+fn dispute_overdue() {
+    let mut disputable = DisputableTransaction::new(
+        DisputableTransactionType::Deposit,
+        Cents::new(1),
+        String::new(),
+        Some(10),
+    );
+    assert!(!disputable.dispute_overdue(100, 5));
+    disputable.dispute(Cents::new(1), Some(20));
+    assert!(!disputable.dispute_overdue(25, 5));
+    assert!(disputable.dispute_overdue(26, 5));
+    disputable.settle(Cents::new(1), false);
+    assert_eq!(None, disputable.disputed_at);
+    assert!(!disputable.dispute_overdue(26, 5));
 }
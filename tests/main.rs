@@ -1,13 +1,16 @@
 use assert_cmd::Command;
-use std::collections::HashSet;
 use std::fs;
-use std::io::{BufRead, BufReader};
 use std::path::Path;
 
 const INPUT_FOLDER: &str = "tests/input";
 const OUTPUT_FOLDER: &str = "tests/output";
+const REJECTS_FOLDER: &str = "tests/rejects";
 
 fn test_csv<P: Clone + AsRef<Path>>(name: P) {
+    test_csv_with_args(name, &[]);
+}
+
+fn test_csv_with_args<P: Clone + AsRef<Path>>(name: P, args: &[&str]) {
     let mut cmd = Command::cargo_bin("transactions").unwrap();
     let res = cmd
         .arg(
@@ -15,25 +18,13 @@ fn test_csv<P: Clone + AsRef<Path>>(name: P) {
                 .join(name.clone())
                 .with_extension("csv"),
         )
+        .args(args)
         .output()
         .unwrap();
     let out = std::str::from_utf8(&res.stdout).unwrap();
-    let expected_file = BufReader::new(
-        fs::File::open(Path::new(OUTPUT_FOLDER).join(name).with_extension("csv")).unwrap(),
-    );
-    let mut expected = HashSet::new();
-    let mut expected_itr = expected_file.lines();
-    expected_itr.next(); // first line is header
-    for line in expected_itr {
-        expected.insert(line.unwrap());
-    }
-    // Remove matching lines from set one by one
-    let mut out_itr = out.lines();
-    out_itr.next(); // first line is header
-    for line in out_itr {
-        assert!(expected.remove(line));
-    }
-    assert!(expected.is_empty());
+    let expected =
+        fs::read_to_string(Path::new(OUTPUT_FOLDER).join(name).with_extension("csv")).unwrap();
+    assert_eq!(expected, out);
 }
 
 #[test]
@@ -50,3 +41,416 @@ fn dispute() {
 fn multiple_failures() {
     test_csv("multiple_failures.csv");
 }
+
+fn test_rejects<P: Clone + AsRef<Path>>(name: P) {
+    test_rejects_with_args(name, &[]);
+}
+
+fn test_rejects_with_args<P: Clone + AsRef<Path>>(name: P, args: &[&str]) {
+    let rejects_path = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join(name.clone())
+        .with_extension("rejects.csv");
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(
+        Path::new(INPUT_FOLDER)
+            .join(name.clone())
+            .with_extension("csv"),
+    )
+    .arg("--rejects")
+    .arg(&rejects_path)
+    .args(args)
+    .output()
+    .unwrap();
+    let out = fs::read_to_string(rejects_path).unwrap();
+    let expected =
+        fs::read_to_string(Path::new(REJECTS_FOLDER).join(name).with_extension("csv")).unwrap();
+    assert_eq!(expected, out);
+}
+
+#[test]
+fn multiple_failures_rejects() {
+    test_rejects("multiple_failures.csv");
+}
+
+#[test]
+fn malformed_abort() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("malformed.csv"))
+        .assert()
+        .code(2)
+        .stdout("");
+}
+
+#[test]
+fn malformed_skip() {
+    test_csv_with_args("malformed.csv", &["--on-parse-error", "skip"]);
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("malformed.csv"))
+        .args(["--on-parse-error", "skip"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn missing_input() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    let assert = cmd
+        .arg(Path::new(INPUT_FOLDER).join("missing.csv"))
+        .assert()
+        .code(5)
+        .stdout("");
+    let stderr = std::str::from_utf8(&assert.get_output().stderr).unwrap();
+    assert!(stderr.starts_with("Failed to open input tests/input/missing.csv: "));
+}
+
+#[test]
+fn unwritable_rejects() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    let assert = cmd
+        .arg(Path::new(INPUT_FOLDER).join("basic.csv"))
+        .args(["--rejects", "tests/missing/rejects.csv"])
+        .assert()
+        .code(7)
+        .stdout("");
+    let stderr = std::str::from_utf8(&assert.get_output().stderr).unwrap();
+    assert!(stderr.starts_with("Failed to create rejects report tests/missing/rejects.csv: "));
+}
+
+#[test]
+fn corrupt_state() {
+    let state_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("corrupt_state.json");
+    fs::write(&state_path, "{\"version\":").unwrap();
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    let assert = cmd
+        .arg(Path::new(INPUT_FOLDER).join("basic.csv"))
+        .arg("--state")
+        .arg(&state_path)
+        .assert()
+        .code(6)
+        .stdout("");
+    let stderr = std::str::from_utf8(&assert.get_output().stderr).unwrap();
+    assert!(stderr.starts_with("Failed to load state "));
+    assert!(stderr.contains("Malformed snapshot"));
+}
+
+#[test]
+fn malformed_report() {
+    test_rejects_with_args("malformed.csv", &["--on-parse-error", "report"]);
+}
+
+#[test]
+fn stdin() {
+    for args in [&[][..], &["-"][..]].iter() {
+        let mut cmd = Command::cargo_bin("transactions").unwrap();
+        let input = fs::read_to_string(Path::new(INPUT_FOLDER).join("dispute.csv")).unwrap();
+        let expected = fs::read_to_string(Path::new(OUTPUT_FOLDER).join("dispute.csv")).unwrap();
+        cmd.args(*args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+}
+
+#[test]
+fn output_file() {
+    let output_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dispute.output.csv");
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("dispute.csv"))
+        .arg("--output")
+        .arg(&output_path)
+        .assert()
+        .success()
+        .stdout("");
+    let expected = fs::read_to_string(Path::new(OUTPUT_FOLDER).join("dispute.csv")).unwrap();
+    assert_eq!(expected, fs::read_to_string(output_path).unwrap());
+}
+
+#[test]
+fn sort_by_total() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("multiple_failures.csv"))
+        .args(["--sort", "total"])
+        .assert()
+        .success()
+        .stdout(
+            "client,available,held,total,locked,status\n\
+             420,0.9999,0.0000,0.9999,false,active\n\
+             69,300.0000,0.0000,300.0000,true,locked",
+        );
+}
+
+#[test]
+fn state() {
+    let state_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("state.json");
+    let _ = fs::remove_file(&state_path);
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("basic.csv"))
+        .arg("--state")
+        .arg(&state_path)
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("next_day.csv"))
+        .arg("--state")
+        .arg(&state_path)
+        .assert()
+        .success()
+        .stdout(
+            "client,available,held,total,locked,status\n\
+             1,1.5000,0.0000,1.5000,false,active\n\
+             2,1.0000,2.0000,3.0000,false,active",
+        );
+}
+
+#[test]
+fn journal_resume() {
+    let journal_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("journal.jsonl");
+    // journal left behind by a run of basic.csv that crashed after applying its first 2 rows
+    fs::write(
+        &journal_path,
+        "{\"entry\":\"transaction\",\"seq\":0,\"line\":2,\
+         \"tx\":{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.0\"}}\n\
+         {\"entry\":\"outcome\",\"seq\":0,\"error\":null}\n\
+         {\"entry\":\"transaction\",\"seq\":1,\"line\":3,\
+         \"tx\":{\"type\":\"deposit\",\"client\":2,\"tx\":2,\"amount\":\"2.0\"}}\n",
+    )
+    .unwrap();
+    test_csv_with_args("basic.csv", &["--journal", journal_path.to_str().unwrap()]);
+    assert!(!journal_path.exists());
+}
+
+#[test]
+fn journal_resume_rejects() {
+    let journal_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rejects_journal.jsonl");
+    // rows rejected before the crash are still reported after resuming
+    fs::write(
+        &journal_path,
+        "{\"entry\":\"transaction\",\"seq\":0,\"line\":2,\
+         \"tx\":{\"type\":\"deposit\",\"client\":69,\"tx\":23,\"amount\":\"420.69\"}}\n\
+         {\"entry\":\"outcome\",\"seq\":0,\"error\":null}\n\
+         {\"entry\":\"transaction\",\"seq\":1,\"line\":3,\
+         \"tx\":{\"type\":\"withdrawal\",\"client\":420,\"tx\":2325,\"amount\":\"10.0\"}}\n\
+         {\"entry\":\"outcome\",\"seq\":1,\"error\":\
+         \"Insufficient Balance. Available: 0.0000. Requested withdrawal: 10.0000\"}\n",
+    )
+    .unwrap();
+    test_rejects_with_args(
+        "multiple_failures.csv",
+        &["--journal", journal_path.to_str().unwrap()],
+    );
+    assert!(!journal_path.exists());
+}
+
+#[test]
+fn journal_diverged() {
+    let state_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("diverged_state.json");
+    let journal_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("diverged_journal.jsonl");
+    let _ = fs::remove_file(&state_path);
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("basic.csv"))
+        .arg("--state")
+        .arg(&state_path)
+        .assert()
+        .success();
+    // a journal whose deposit is already in the state cannot be replayed onto it
+    fs::write(
+        &journal_path,
+        "{\"entry\":\"transaction\",\"seq\":0,\"line\":2,\
+         \"tx\":{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.0\"}}\n\
+         {\"entry\":\"outcome\",\"seq\":0,\"error\":null}\n",
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    let assert = cmd
+        .arg(Path::new(INPUT_FOLDER).join("basic.csv"))
+        .arg("--state")
+        .arg(&state_path)
+        .arg("--journal")
+        .arg(&journal_path)
+        .assert()
+        .code(4)
+        .stdout("");
+    let stderr = std::str::from_utf8(&assert.get_output().stderr).unwrap();
+    assert_eq!(
+        "Replaying journal entry 0 did not reproduce its recorded outcome\n",
+        stderr
+    );
+}
+
+#[test]
+fn threads() {
+    test_csv_with_args(
+        "multiple_failures.csv",
+        &["--threads", "4", "--per-client-tx-ids"],
+    );
+    test_rejects_with_args(
+        "multiple_failures.csv",
+        &["--threads", "4", "--per-client-tx-ids"],
+    );
+}
+
+#[test]
+fn threads_require_per_client_tx_ids() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("basic.csv"))
+        .args(["--threads", "4"])
+        .assert()
+        .failure()
+        .stdout("");
+}
+
+#[test]
+fn assets() {
+    test_csv_with_args("assets.csv", &["--asset-precision", "USD=2"]);
+}
+
+#[test]
+fn precision() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg("--precision")
+        .arg("8")
+        .write_stdin(
+            "type,client,tx,amount\n\
+             deposit,1,1,0.12345678\n\
+             withdrawal,1,2,0.00000008\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "client,available,held,total,locked,status\n\
+             1,0.12345670,0.00000000,0.12345670,false,active",
+        );
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.args(["--precision", "2", "--on-parse-error", "skip"])
+        .write_stdin(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.5\n\
+             deposit,1,2,1.234\n",
+        )
+        .assert()
+        .code(3)
+        .stdout(
+            "client,available,held,total,locked,status\n\
+             1,1.50,0.00,1.50,false,active",
+        );
+}
+
+#[test]
+fn overflow() {
+    test_csv("overflow.csv");
+    test_rejects("overflow.csv");
+}
+
+#[test]
+fn admin() {
+    test_csv("admin.csv");
+    test_rejects("admin.csv");
+}
+
+#[test]
+fn transfer() {
+    test_csv("transfer.csv");
+    test_rejects("transfer.csv");
+    // clients 1 and 2 are on different threads
+    let args = ["--threads", "2", "--per-client-tx-ids"];
+    test_csv_with_args("transfer.csv", &args);
+    test_rejects_with_args("transfer.csv", &args);
+}
+
+#[test]
+fn partial_dispute() {
+    test_csv("partial_dispute.csv");
+    test_rejects("partial_dispute.csv");
+}
+
+#[test]
+fn fees() {
+    test_csv("fees.csv");
+    test_rejects("fees.csv");
+}
+
+#[test]
+fn negative_balance() {
+    test_csv_with_args("negative_balance.csv", &["--allow-negative"]);
+    test_rejects_with_args("negative_balance.csv", &["--allow-negative"]);
+}
+
+#[test]
+fn authorization() {
+    test_csv_with_args("authorization.csv", &["--auth-expiry", "3"]);
+    test_rejects_with_args("authorization.csv", &["--auth-expiry", "3"]);
+}
+
+#[test]
+fn timestamps() {
+    let args = ["--dispute-window", "86400", "--auto-resolve", "3600"];
+    test_csv_with_args("timestamps.csv", &args);
+    test_rejects_with_args("timestamps.csv", &args);
+}
+
+#[test]
+fn json_lines() {
+    let input = Path::new(INPUT_FOLDER).join("json_lines.jsonl");
+    let expected = fs::read_to_string(Path::new(OUTPUT_FOLDER).join("json_lines.csv")).unwrap();
+    let rejects_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("json_lines.rejects.csv");
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(&input)
+        .args(["--on-parse-error", "report", "--rejects"])
+        .arg(&rejects_path)
+        .assert()
+        .code(3)
+        .stdout(expected.clone());
+    let expected_rejects =
+        fs::read_to_string(Path::new(REJECTS_FOLDER).join("json_lines.csv")).unwrap();
+    assert_eq!(expected_rejects, fs::read_to_string(rejects_path).unwrap());
+    // the format cannot be inferred from stdin
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.args(["--input-format", "ndjson", "--on-parse-error", "skip"])
+        .write_stdin(fs::read_to_string(input).unwrap())
+        .assert()
+        .code(3)
+        .stdout(expected);
+}
+
+#[test]
+fn output_format() {
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.arg(Path::new(INPUT_FOLDER).join("dispute.csv"))
+        .args(["--output-format", "ndjson"])
+        .assert()
+        .success()
+        .stdout(
+            "{\"client\":1,\"available\":\"0.0000\",\"held\":\"1.0000\",\"total\":\"1.0000\",\
+             \"locked\":false,\"status\":\"active\",\"open_disputes\":1,\"charged_back\":0}\n",
+        );
+}
+
+#[test]
+fn column_aliases() {
+    let input = "txid,client_id,type,value\n\
+                 1,1,deposit,1.0\n\
+                 1,1,dispute,\n";
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    cmd.args([
+        "--column-alias",
+        "client_id=client",
+        "--column-alias",
+        "txid=tx",
+    ])
+    .args(["--column-alias", "value=amount"])
+    .write_stdin(input)
+    .assert()
+    .success()
+    .stdout(fs::read_to_string(Path::new(OUTPUT_FOLDER).join("dispute.csv")).unwrap());
+    // a missing column fails before any row is processed, even when skipping bad rows
+    let mut cmd = Command::cargo_bin("transactions").unwrap();
+    let assert = cmd
+        .args(["--on-parse-error", "skip"])
+        .write_stdin(input)
+        .assert()
+        .code(2)
+        .stdout("");
+    let stderr = std::str::from_utf8(&assert.get_output().stderr).unwrap();
+    assert!(stderr.starts_with("Line 1: Invalid header: Unknown column: txid\n"));
+}